@@ -1,19 +1,198 @@
-#[derive(Debug, Clone, Eq, PartialEq)]
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+
+#[derive(Debug, Clone)]
 pub struct Position {
     start: (usize, usize), // (row, col)
     end:   (usize, usize),
+    start_byte: usize,     // byte offsets into the lexed source, end exclusive
+    end_byte:   usize,
+    source_id:  u32,       // LexerOptions::source_id of the lexed source
+}
+
+// byte offsets follow from (row, col) within one source, so they are left out of comparisons
+impl PartialEq for Position {
+    fn eq(&self, other: &Position) -> bool {
+        self.source_id == other.source_id && self.start == other.start && self.end == other.end
+    }
+}
+
+impl Eq for Position {}
+
+impl Position {
+    fn new(start: (usize, usize), end: (usize, usize)) -> Position {
+        Position { start, end, start_byte: 0, end_byte: 0, source_id: 0 }
+    }
+
+    fn in_source(mut self, source_id: u32) -> Position {
+        self.source_id = source_id;
+        self
+    }
+
+    pub fn start(&self) -> (usize, usize) {
+        self.start
+    }
+
+    pub fn end(&self) -> (usize, usize) {
+        self.end
+    }
+
+    pub fn row(&self) -> usize {
+        self.start.0
+    }
+
+    pub fn col(&self) -> usize {
+        self.start.1
+    }
+
+    pub fn source_id(&self) -> u32 {
+        self.source_id
+    }
+
+    fn with_bytes(mut self, start_byte: usize, end_byte: usize) -> Position {
+        (self.start_byte, self.end_byte) = (start_byte, end_byte);
+        self
+    }
+
+    pub fn byte_range(&self) -> Range<usize> {
+        self.start_byte..self.end_byte
+    }
+
+    pub fn byte_len(&self) -> usize {
+        self.end_byte - self.start_byte
+    }
+
+    // both ends are inclusive; comparing (row, col) pairs puts every col of the rows in between inside
+    pub fn contains(&self, row: usize, col: usize) -> bool {
+        self.start <= (row, col) && (row, col) <= self.end
+    }
+
+    pub fn is_multiline(&self) -> bool {
+        self.start.0 != self.end.0
+    }
+
+    // columns covered by a single-line span, counting both ends; a multi-line span has no one width and gives 0
+    pub fn width(&self) -> usize {
+        if self.is_multiline() { return 0; }
+        (self.end.1 - self.start.1).saturating_add(1)
+    }
+
+    fn shift_bytes(&self, by: usize) -> Position {
+        self.clone().with_bytes(self.start_byte + by, self.end_byte + by)
+    }
+
+    // moves both ends, clamping at 0 rather than wrapping; byte offsets still refer to the lexed source
+    pub fn shift(&self, row_delta: isize, col_delta: isize) -> Position {
+        let shift = |(row, col): (usize, usize)| (row.saturating_add_signed(row_delta), col.saturating_add_signed(col_delta));
+        Position::new(shift(self.start), shift(self.end)).with_bytes(self.start_byte, self.end_byte).in_source(self.source_id)
+    }
+
+    // the smallest span covering both, in either order; bytes come along with whichever end they belong to.
+    // panics if the two are from different sources, which no span can cover
+    pub fn merge(&self, other: &Position) -> Position {
+        assert_eq!(self.source_id, other.source_id, "merging positions from different sources");
+        let first = if other.start < self.start { other } else { self };
+        let last = if other.end > self.end { other } else { self };
+        Position::new(first.start, last.end).with_bytes(first.start_byte, last.end_byte).in_source(self.source_id)
+    }
+
+    // `end` is exclusive, as with ranges produced by str::find or regex matches
+    pub fn from_byte_offsets(start: usize, end: usize, index: &LineIndex) -> Position {
+        let last = index.source[..end].chars().next_back().map_or(0, char::len_utf8);
+        let (start_pos, end_pos) = (index.line_col(start), index.line_col(if end > start { end - last } else { start }));
+        Position::new(start_pos, end_pos).with_bytes(start, end)
+    }
+}
+
+// `3:5..3:7`, or just `3:5` for a single char
+impl std::fmt::Display for Position {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let ((start_row, start_col), (end_row, end_col)) = (self.start, self.end);
+        if self.start == self.end {
+            write!(f, "{start_row}:{start_col}")
+        } else {
+            write!(f, "{start_row}:{start_col}..{end_row}:{end_col}")
+        }
+    }
+}
+
+// byte offsets of every line start, for mapping byte offsets back to (row, col)
+#[derive(Debug, Clone)]
+pub struct LineIndex<'a> {
+    source: &'a str,
+    line_starts: Vec<usize>,
+    tab_width: usize,
+}
+
+impl<'a> LineIndex<'a> {
+    pub fn new(source: &'a str) -> LineIndex<'a> {
+        let mut line_starts = vec![0];
+        line_starts.extend(source.match_indices('\n').map(|(i, _)| i + 1));
+        LineIndex { source, line_starts, tab_width: 1 }
+    }
+
+    // for when the lexer has already found the lines
+    fn from_lines(source: &'a str, lines: &[(usize, usize)]) -> LineIndex<'a> {
+        LineIndex { source, line_starts: lines.iter().map(|&(start, _)| start).collect(), tab_width: 1 }
+    }
+
+    // columns count tabs up to the next tab stop, as LexerOptions::tab_width does
+    pub fn with_tab_width(mut self, tab_width: usize) -> LineIndex<'a> {
+        self.tab_width = tab_width.max(1);
+        self
+    }
+
+    // the column right after `c` at `col`
+    fn next_col(&self, col: usize, c: char) -> usize {
+        Columns { first: 1, tab_width: self.tab_width }.after((1, col), c).1
+    }
+
+    pub fn line_col(&self, offset: usize) -> (usize, usize) {
+        let row = self.line_starts.partition_point(|&start| start <= offset);
+        let line_start = self.line_starts[row - 1];
+        (row, self.source[line_start..offset].chars().fold(1, |col, c| self.next_col(col, c)))
+    }
+
+    // inverse of line_col, positions past the end of a line or the source clamp to the end of the source;
+    // a column inside a tab's width maps to the tab
+    pub fn offset(&self, (row, col): (usize, usize)) -> usize {
+        let Some(&line_start) = self.line_starts.get(row.wrapping_sub(1)) else { return self.source.len() };
+        let mut at = 1;
+        for (i, c) in self.source[line_start..].char_indices() {
+            at = self.next_col(at, c);
+            if at > col {
+                return line_start + i;
+            }
+        }
+        self.source.len()
+    }
 }
 
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub enum Token {
     Identifier(Position, String),  // [A-Za-z_][A-Za-z0-9_]*
+    Keyword(Position, String),     // identifier listed in LexerOptions::keywords
+    RawIdentifier(Position, String), // 'r#'[A-Za-z_][A-Za-z0-9_]*, stores the name without `r#`
     NumLiteral(Position, String),  // [0-9]+
     StrLiteral(Position, String),  // '"'[...]'"' TODO: figure this out 
+    TaggedStrLiteral(Position, Box<(String, String)>), // tag'"'[...]'"' as (tag, value), boxed so the rare second String doesn't grow every Token
+    Attribute(Position, Box<(String, Vec<Token>)>), // '@'name['('...')'] as (name, argument tokens), see LexerOptions::attributes
+    PreprocessorDirective(Position, String), // a whole `#define X 1` line, see LexerOptions::preprocessor_directives
     LParen(Position),              // (
     RParen(Position),              // )
     LBrace(Position),              // {
     RBrace(Position),              // }
+    Semicolon(Position),           // LexerOptions::statement_terminator, `;` by default
+    Comma(Position),               // ,
+    Colon(Position),               // :
+    ColonColon(Position),          // ::
+    Dot(Position),                 // .
+    DotDot(Position),              // ..
+    DotDotEq(Position),            // ..=
+    Ellipsis(Position),            // ...
     Arrow(Position),               // ->
+    ArrowGt(Position),             // ->>, see LexerOptions::pipeline_arrows
     FatArrow(Position),            // =>
     Eq(Position),                  // =
     EqEq(Position),                // ==
@@ -28,6 +207,7 @@ pub enum Token {
     ModEq(Position),               // %=
     RShiftEq(Position),            // >>=
     LShiftEq(Position),            // <<=
+    LShiftSub(Position),           // <<-, see LexerOptions::pipeline_arrows
     RShift(Position),              // >>
     LShift(Position),              // <<
     NotEq(Position),               // !=
@@ -45,280 +225,1656 @@ pub enum Token {
     Xor(Position),                 // ^
     Or(Position),                  // |
     OrOr(Position),                // ||
+    PipeForward(Position),         // |>, see LexerOptions::pipe_forward
     And(Position),                 // &
     AndAnd(Position),              // &&
+    Question(Position),            // ?
+    QuestionQuestion(Position),    // ??
+    QuestionQuestionEq(Position),  // ??=
+    QuestionDot(Position),         // ?.
+    Error(Position, String),       // unlexable input, only produced by tokenize_all
     Eof(Position),
 }
 
-macro_rules! next_and {
-    ($iter:ident, $ret:ident) => {
-        {
-            $iter.next();
-            $ret
-        }
-    };
+// tags are stable across releases for FFI; new kinds take the next free number
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+#[repr(u16)]
+pub enum TokenKind {
+    Identifier = 0,
+    Keyword = 1,
+    RawIdentifier = 2,
+    NumLiteral = 3,
+    StrLiteral = 4,
+    TaggedStrLiteral = 5,
+    Attribute = 6,
+    LParen = 7,
+    RParen = 8,
+    LBrace = 9,
+    RBrace = 10,
+    Semicolon = 11,
+    Comma = 12,
+    Dot = 13,
+    DotDot = 14,
+    DotDotEq = 15,
+    Ellipsis = 16,
+    Arrow = 17,
+    FatArrow = 18,
+    Eq = 19,
+    EqEq = 20,
+    Lt = 21,
+    Gt = 22,
+    LtEq = 23,
+    GtEq = 24,
+    AddEq = 25,
+    SubEq = 26,
+    MulEq = 27,
+    DivEq = 28,
+    ModEq = 29,
+    RShiftEq = 30,
+    LShiftEq = 31,
+    RShift = 32,
+    LShift = 33,
+    NotEq = 34,
+    OrEq = 35,
+    AndEq = 36,
+    XorEq = 37,
+    Add = 38,
+    Sub = 39,
+    Mul = 40,
+    Div = 41,
+    Mod = 42,
+    AddAdd = 43,
+    SubSub = 44,
+    Not = 45,
+    Xor = 46,
+    Or = 47,
+    OrOr = 48,
+    And = 49,
+    AndAnd = 50,
+    Eof = 51,
+    Error = 52,
+    Question = 53,
+    QuestionQuestion = 54,
+    QuestionQuestionEq = 55,
+    QuestionDot = 56,
+    PreprocessorDirective = 57,
+    Colon = 58,
+    ColonColon = 59,
+    PipeForward = 60,
+    ArrowGt = 61,
+    LShiftSub = 62,
+}
 
-    ($iter:ident, $ret:expr) => {
-        {
-            $iter.next();
-            $ret
-        }
-    };
+impl TokenKind {
+    // indexed by tag
+    pub const ALL: [TokenKind; 63] = [
+        TokenKind::Identifier, TokenKind::Keyword, TokenKind::RawIdentifier, TokenKind::NumLiteral,
+        TokenKind::StrLiteral, TokenKind::TaggedStrLiteral, TokenKind::Attribute, TokenKind::LParen,
+        TokenKind::RParen, TokenKind::LBrace, TokenKind::RBrace, TokenKind::Semicolon,
+        TokenKind::Comma, TokenKind::Dot, TokenKind::DotDot, TokenKind::DotDotEq,
+        TokenKind::Ellipsis, TokenKind::Arrow, TokenKind::FatArrow, TokenKind::Eq, TokenKind::EqEq,
+        TokenKind::Lt, TokenKind::Gt, TokenKind::LtEq, TokenKind::GtEq, TokenKind::AddEq,
+        TokenKind::SubEq, TokenKind::MulEq, TokenKind::DivEq, TokenKind::ModEq, TokenKind::RShiftEq,
+        TokenKind::LShiftEq, TokenKind::RShift, TokenKind::LShift, TokenKind::NotEq,
+        TokenKind::OrEq, TokenKind::AndEq, TokenKind::XorEq, TokenKind::Add, TokenKind::Sub,
+        TokenKind::Mul, TokenKind::Div, TokenKind::Mod, TokenKind::AddAdd, TokenKind::SubSub,
+        TokenKind::Not, TokenKind::Xor, TokenKind::Or, TokenKind::OrOr, TokenKind::And,
+        TokenKind::AndAnd, TokenKind::Eof, TokenKind::Error, TokenKind::Question,
+        TokenKind::QuestionQuestion, TokenKind::QuestionQuestionEq, TokenKind::QuestionDot,
+        TokenKind::PreprocessorDirective, TokenKind::Colon, TokenKind::ColonColon,
+        TokenKind::PipeForward, TokenKind::ArrowGt, TokenKind::LShiftSub,
+    ];
+
+    pub fn tag(self) -> u16 {
+        self as u16
+    }
+
+    pub fn from_tag(tag: u16) -> Option<TokenKind> {
+        TokenKind::ALL.get(tag as usize).copied()
+    }
 }
 
-pub fn tokenize(input: String) -> Vec<Token> {
-    let (mut row, mut col) = (1, 1);
-    let mut output: Vec<Token> = vec![];
-    let mut chars = input.chars().into_iter().peekable();
-    while let Some(char) = chars.next() {
-        match char {
-            ' ' | '\n' | '\t' => {
-                if char == '\n' {
-                    row += 1;
-                    col = 1;
-                }
-            },
-            c if c.is_ascii_alphabetic() || c == '_' => {
-                let start = (row, col);
-                let mut val = String::from(char);
-                while let Some(n) = chars.peek() {
-                    if !n.is_ascii_alphanumeric() && *n != '_' { break; }
-                    col += 1;
-                    val.push(*n);
-                    chars.next();
-                }
+// the variant name, e.g. "Arrow"
+impl std::fmt::Display for TokenKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{self:?}")
+    }
+}
 
-                output.push(Token::Identifier(Position{ start, end: (row, col) }, val));
-            },
-            //TODO: decimals
-            c if c.is_ascii_digit() => {
-                let start = (row, col);
-                let mut val = String::from(char);
-                while let Some(n) = chars.peek() {
-                    if !n.is_ascii_digit() { break; }
-                    col += 1;
-                    val.push(*n);
-                    chars.next();
-                }
+impl std::str::FromStr for TokenKind {
+    type Err = String;
 
-                output.push(Token::NumLiteral(Position{ start, end: (row, col) }, val));
-            },
-            '"' => {
-                let start = (row, col);
-                let mut val = String::new();
-                let mut terminated = false;
-                while let Some(&n) = chars.peek() {
-                    chars.next();
-                    col += 1;
-                    if n == '"' {
-                        terminated = true;
-                        break;
-                    }
+    fn from_str(name: &str) -> Result<TokenKind, String> {
+        TokenKind::ALL.into_iter().find(|kind| kind.to_string() == name).ok_or_else(|| format!("unknown token kind `{name}`"))
+    }
+}
 
-                    val.push(n);
-                }
+impl Token {
+    pub fn is_eof(&self) -> bool {
+        matches!(self, Token::Eof(_))
+    }
 
-                if !terminated { panic!("ERROR: string literal not terminated at {row}:{col}"); }
-                output.push(Token::StrLiteral(Position{ start, end: (row, col) }, val));
-            },
-            '(' => output.push(Token::LParen(Position{ start: (row, col), end: (row, col) })),
-            ')' => output.push(Token::RParen(Position{ start: (row, col), end: (row, col) })),
-            '{' => output.push(Token::LBrace(Position{ start: (row, col), end: (row, col) })),
-            '}' => output.push(Token::RBrace(Position{ start: (row, col), end: (row, col) })),
-            '<' => {
-                let mut cur = Token::Lt(Position{ start: (row, col), end: (row, col) });
-                if let Some(next) = chars.peek() {
-                    cur = match next {
-                        '=' => next_and!(chars, Token::LtEq(Position{ start: (row, col), end: (row + 1, col + 1) })),
-                        '<' => {
-                            chars.next();
-                            if let Some('=') = chars.peek() {
-                                next_and!(chars, Token::LShiftEq(Position{ start: (row, col), end: (row + 2, col + 2) }))
-                            } else {
-                                Token::LShift(Position{ start: (row, col), end: (row + 1, col + 1) })
-                            }
-                        }
-                        _ => cur,
-                    };
-                }
-                output.push(cur);
-            },
-            '>' => {
-                let mut cur = Token::Gt(Position{ start: (row, col), end: (row, col) });
-                if let Some(next) = chars.peek() {
-                    cur = match next {
-                        '=' => next_and!(chars, Token::GtEq(Position{ start: (row, col), end: (row + 1, col + 1) })),
-                        '>' => {
-                            chars.next();
-                            if let Some('=') = chars.peek() {
-                                next_and!(chars, Token::RShiftEq(Position{ start: (row, col), end: (row + 2, col + 2) })) }
-                            else {
-                                Token::RShift(Position{ start: (row, col), end: (row + 1, col + 1) })
-                            }
-                        }
-                        _ => cur,
-                    };
-                }
-                output.push(cur);
-            },
-            '+' => {
-                let mut cur = Token::Add(Position{ start: (row, col), end: (row, col) });
-                if let Some(next) = chars.peek() {
-                    cur = match next {
-                        '+' => next_and!(chars, Token::AddAdd(Position{ start: (row, col), end: (row + 1, col + 1) })),
-                        '=' => next_and!(chars, Token::AddEq(Position{ start: (row, col), end: (row + 1, col + 1) })),
-                        _ => cur,
-                    };
-                }
-                output.push(cur);
-            },
-            '-' => {
-                let mut cur = Token::Sub(Position{ start: (row, col), end: (row, col) });
-                if let Some(next) = chars.peek() {
-                    cur = match next {
-                        '-' => next_and!(chars, Token::SubSub(Position{ start: (row, col), end: (row + 1, col + 1) })),
-                        '=' => next_and!(chars, Token::SubEq(Position{ start: (row, col), end: (row + 1, col + 1) })),
-                        '>' => next_and!(chars, Token::Arrow(Position{ start: (row, col), end: (row + 1, col + 1) })),
-                        _ => cur,
-                    };
-                }
-                output.push(cur);
-            },
-            '*' => {
-                let mut cur = Token::Mul(Position{ start: (row, col), end: (row, col) });
-                if let Some(next) = chars.peek() {
-                    cur = match next {
-                        '=' => next_and!(chars, Token::MulEq(Position{ start: (row, col), end: (row + 1, col + 1) })),
-                        _ => cur,
-                    }
-                }
-                output.push(cur);
-            },
-            '/' => {
-                let mut cur = Token::Div(Position{ start: (row, col), end: (row, col) });
-                if let Some(next) = chars.peek() {
-                    cur = match next {
-                        '/' => {
-                            loop {
-                                match chars.peek() {
-                                    Some('\n') => break,
-                                    None => break,
-                                    _ => next_and!(chars, continue),
-                                }
-                            }
+    pub fn category_tag(&self) -> u16 {
+        self.kind().tag()
+    }
 
-                            continue
-                        },
-                        '*' => {
-                            loop {
-                                match (chars.next(), chars.peek()) {
-                                    (Some('*'), Some('/')) => break,
-                                    (Some(_), Some(_)) => continue,
-                                     _ => panic!("ERROR: comment block not terminated at {row}:{col}"),
-                                }
-                            }
+    pub fn kind(&self) -> TokenKind {
+        match self {
+            Token::Identifier(_, _) => TokenKind::Identifier,
+            Token::Keyword(_, _) => TokenKind::Keyword,
+            Token::RawIdentifier(_, _) => TokenKind::RawIdentifier,
+            Token::NumLiteral(_, _) => TokenKind::NumLiteral,
+            Token::StrLiteral(_, _) => TokenKind::StrLiteral,
+            Token::TaggedStrLiteral(_, _) => TokenKind::TaggedStrLiteral,
+            Token::Attribute(_, _) => TokenKind::Attribute,
+            Token::PreprocessorDirective(_, _) => TokenKind::PreprocessorDirective,
+            Token::Error(_, _) => TokenKind::Error,
+            Token::LParen(_) => TokenKind::LParen,
+            Token::RParen(_) => TokenKind::RParen,
+            Token::LBrace(_) => TokenKind::LBrace,
+            Token::RBrace(_) => TokenKind::RBrace,
+            Token::Semicolon(_) => TokenKind::Semicolon,
+            Token::Comma(_) => TokenKind::Comma,
+            Token::Colon(_) => TokenKind::Colon,
+            Token::ColonColon(_) => TokenKind::ColonColon,
+            Token::Dot(_) => TokenKind::Dot,
+            Token::DotDot(_) => TokenKind::DotDot,
+            Token::DotDotEq(_) => TokenKind::DotDotEq,
+            Token::Ellipsis(_) => TokenKind::Ellipsis,
+            Token::Arrow(_) => TokenKind::Arrow,
+            Token::ArrowGt(_) => TokenKind::ArrowGt,
+            Token::FatArrow(_) => TokenKind::FatArrow,
+            Token::Eq(_) => TokenKind::Eq,
+            Token::EqEq(_) => TokenKind::EqEq,
+            Token::Lt(_) => TokenKind::Lt,
+            Token::Gt(_) => TokenKind::Gt,
+            Token::LtEq(_) => TokenKind::LtEq,
+            Token::GtEq(_) => TokenKind::GtEq,
+            Token::AddEq(_) => TokenKind::AddEq,
+            Token::SubEq(_) => TokenKind::SubEq,
+            Token::MulEq(_) => TokenKind::MulEq,
+            Token::DivEq(_) => TokenKind::DivEq,
+            Token::ModEq(_) => TokenKind::ModEq,
+            Token::RShiftEq(_) => TokenKind::RShiftEq,
+            Token::LShiftEq(_) => TokenKind::LShiftEq,
+            Token::LShiftSub(_) => TokenKind::LShiftSub,
+            Token::RShift(_) => TokenKind::RShift,
+            Token::LShift(_) => TokenKind::LShift,
+            Token::NotEq(_) => TokenKind::NotEq,
+            Token::OrEq(_) => TokenKind::OrEq,
+            Token::AndEq(_) => TokenKind::AndEq,
+            Token::XorEq(_) => TokenKind::XorEq,
+            Token::Add(_) => TokenKind::Add,
+            Token::Sub(_) => TokenKind::Sub,
+            Token::Mul(_) => TokenKind::Mul,
+            Token::Div(_) => TokenKind::Div,
+            Token::Mod(_) => TokenKind::Mod,
+            Token::AddAdd(_) => TokenKind::AddAdd,
+            Token::SubSub(_) => TokenKind::SubSub,
+            Token::Not(_) => TokenKind::Not,
+            Token::Xor(_) => TokenKind::Xor,
+            Token::Or(_) => TokenKind::Or,
+            Token::OrOr(_) => TokenKind::OrOr,
+            Token::PipeForward(_) => TokenKind::PipeForward,
+            Token::And(_) => TokenKind::And,
+            Token::AndAnd(_) => TokenKind::AndAnd,
+            Token::Question(_) => TokenKind::Question,
+            Token::QuestionQuestion(_) => TokenKind::QuestionQuestion,
+            Token::QuestionQuestionEq(_) => TokenKind::QuestionQuestionEq,
+            Token::QuestionDot(_) => TokenKind::QuestionDot,
+            Token::Eof(_) => TokenKind::Eof,
+        }
+    }
 
-                            continue
-                        },
-                        '=' => next_and!(chars, Token::DivEq(Position{ start: (row, col), end: (row + 1, col + 1) })),
-                        _ => cur,
-                    };
-                }
-                output.push(cur);
-            },
-            '=' => {
-                let mut cur = Token::Eq(Position{ start: (row, col), end: (row, col) });
-                if let Some(next) = chars.peek() {
-                    cur = match next {
-                        '=' => next_and!(chars, Token::EqEq(Position{ start: (row, col), end: (row + 1, col + 1) })),
-                        '>' => next_and!(chars, Token::FatArrow(Position{ start: (row, col), end: (row + 1, col + 1) })),
-                        _ => cur,
-                    };
-                } 
-                output.push(cur);
-            },
-            '!' => {
-                let mut cur = Token::Not(Position{ start: (row, col), end: (row, col) });
-                if let Some(next) = chars.peek() {
-                    cur = match next {
-                        '=' => next_and!(chars, Token::NotEq(Position{ start: (row, col), end: (row + 1, col + 1) })),
-                        _ => cur,
-                    }
-                }
-                output.push(cur);
-            },
-            '|' => {
-                let mut cur = Token::Or(Position{ start: (row, col), end: (row, col) });
-                if let Some(next) = chars.peek() {
-                    cur = match next {
-                        '=' => next_and!(chars, Token::OrEq(Position{ start: (row, col), end: (row + 1, col + 1) })),
-                        '|' => next_and!(chars, Token::OrOr(Position{ start: (row, col), end: (row + 1, col + 1) })),
-                        _ => cur,
-                    }
-                }
-                output.push(cur);
-            },
-            '&' => {
-                let mut cur = Token::And(Position{ start: (row, col), end: (row, col) });
-                if let Some(next) = chars.peek() {
-                    cur = match next {
-                        '=' => next_and!(chars, Token::AndEq(Position{ start: (row, col), end: (row + 1, col + 1) })),
-                        '&' => next_and!(chars, Token::AndAnd(Position{ start: (row, col), end: (row + 1, col + 1) })),
-                        _ => cur,
-                    }
-                } 
-                output.push(cur);
-            },
-            '^' => {
-                let mut cur = Token::Xor(Position{ start: (row, col), end: (row, col) });
-                if let Some(next) = chars.peek() {
-                    cur = match next {
-                        '=' => next_and!(chars, Token::XorEq(Position{ start: (row, col), end: (row + 1, col + 1) })),
-                        _ => cur,
-                    }
-                }
-                output.push(cur);
-            },
-            '%' => {
-                let mut cur = Token::Mod(Position{ start: (row, col), end: (row, col) });
-                if let Some(next) = chars.peek() {
-                    cur = match next {
-                        '=' => next_and!(chars, Token::ModEq(Position{ start: (row, col), end: (row + 1, col + 1) })),
-                        _ => cur,
-                    };
-                }
-                output.push(cur);
-            },
-            _ => panic!("ERROR: unknown char `{char}` at {row}:{col}"),
-        };
+    pub fn is_open_delimiter(&self) -> bool {
+        matches!(self, Token::LParen(_) | Token::LBrace(_))
+    }
 
-        col += 1;
+    pub fn is_close_delimiter(&self) -> bool {
+        matches!(self, Token::RParen(_) | Token::RBrace(_))
     }
 
-    output.push(Token::Eof(Position { start: (row, col), end: (row, col) }));
-    output
-}
+    pub fn is_int_literal(&self) -> bool {
+        matches!(self, Token::NumLiteral(..))
+    }
 
-#[cfg(test)]
-mod tests {
-    use std::mem::discriminant;
-    use super::*;
+    pub fn is_string_literal(&self) -> bool {
+        matches!(self, Token::StrLiteral(..) | Token::TaggedStrLiteral(..))
+    }
 
-    macro_rules! variant_eq {
-        ($left:ident, $right:ident) => { discriminant(&$left) == discriminant(&$right) };
-        ($left:ident, $right:expr) =>  { discriminant(&$left) == discriminant(&$right) };
-        ($left:expr, $right:ident) =>  { discriminant(&$left) == discriminant(&$right) };
-        ($left:expr, $right:expr) =>   { discriminant(&$left) == discriminant(&$right) };
+    // the name an identifier refers to, `type` for both `type` and `r#type`
+    pub fn identifier_name(&self) -> Option<&str> {
+        match self {
+            Token::Identifier(_, name) | Token::RawIdentifier(_, name) => Some(name),
+            _ => None,
+        }
     }
 
+    // the identifier as written in the source
+    pub fn identifier_raw_spelling(&self) -> Option<String> {
+        match self {
+            Token::Identifier(_, name) => Some(name.clone()),
+            Token::RawIdentifier(_, name) => Some(format!("r#{name}")),
+            _ => None,
+        }
+    }
 
-    #[test]
-    fn operators() {
-        let input = "-> => == <= >= += -= *= /= %= >>= <<= >> << != |= &= ^= ++ -- || &&".to_string();
-        let tokens = tokenize(input);
-        let mut token = tokens.iter();
-        let pos = Position { start: (0, 0), end: (0, 0) };
-        assert!(variant_eq!(*token.next().unwrap(), Token::Arrow(pos.clone())));
+    // only tokens that group or separate: brackets, `;`, `,`, `:`, and the path separators `::` and `.`.
+    // anything that does something with its operands is an operator and gets None, so `?.`, ranges
+    // like `..` and `...`, and arrows all do. A Semicolon is `;` even with another statement_terminator
+    pub fn punctuation_str(&self) -> Option<&'static str> {
+        match self {
+            Token::LParen(_)     => Some("("),
+            Token::RParen(_)     => Some(")"),
+            Token::LBrace(_)     => Some("{"),
+            Token::RBrace(_)     => Some("}"),
+            Token::Semicolon(_)  => Some(";"),
+            Token::Comma(_)      => Some(","),
+            Token::Colon(_)      => Some(":"),
+            Token::ColonColon(_) => Some("::"),
+            Token::Dot(_)        => Some("."),
+            _ => None,
+        }
+    }
+
+    // formatter spacing between `prev` and `self`: nothing inside parens, before `,` or a call's `(`,
+    // after `!` or around ++/-- next to their operand, a single space everywhere else.
+    // formatters wanting other rules can match their special cases first and fall back to this
+    pub fn requires_space_before(&self, prev: &Token) -> bool {
+        !matches!((prev, self),
+            (_, Token::Eof(_))
+            | (Token::LParen(_), _) | (_, Token::RParen(_)) | (_, Token::Comma(_))
+            | (Token::Identifier(..) | Token::RawIdentifier(..) | Token::RParen(_), Token::LParen(_))
+            | (Token::Identifier(..) | Token::RawIdentifier(..) | Token::RParen(_), Token::AddAdd(_) | Token::SubSub(_))
+            | (Token::AddAdd(_) | Token::SubSub(_), Token::Identifier(..) | Token::RawIdentifier(..))
+            | (Token::Not(_), _)
+            | (Token::Dot(_) | Token::QuestionDot(_), _) | (_, Token::Dot(_) | Token::QuestionDot(_)))
+    }
+
+    pub fn position(&self) -> &Position {
+        match self {
+            Token::Identifier(pos, _) | Token::Keyword(pos, _) | Token::RawIdentifier(pos, _) => pos,
+            Token::NumLiteral(pos, _) | Token::StrLiteral(pos, _) => pos,
+            Token::TaggedStrLiteral(pos, _) | Token::Attribute(pos, _) | Token::Error(pos, _) => pos,
+            Token::PreprocessorDirective(pos, _) => pos,
+            Token::LParen(pos) | Token::RParen(pos) | Token::LBrace(pos) | Token::RBrace(pos) | Token::Arrow(pos) | Token::FatArrow(pos)
+            | Token::Eq(pos) | Token::EqEq(pos) | Token::Lt(pos) | Token::Gt(pos) | Token::LtEq(pos) | Token::GtEq(pos)
+            | Token::AddEq(pos) | Token::SubEq(pos) | Token::MulEq(pos) | Token::DivEq(pos) | Token::ModEq(pos) | Token::RShiftEq(pos)
+            | Token::LShiftEq(pos) | Token::RShift(pos) | Token::LShift(pos) | Token::NotEq(pos) | Token::OrEq(pos) | Token::AndEq(pos)
+            | Token::XorEq(pos) | Token::Add(pos) | Token::Sub(pos) | Token::Mul(pos) | Token::Div(pos) | Token::Mod(pos)
+            | Token::AddAdd(pos) | Token::SubSub(pos) | Token::Not(pos) | Token::Xor(pos) | Token::Or(pos) | Token::OrOr(pos)
+            | Token::And(pos) | Token::AndAnd(pos) | Token::Semicolon(pos)
+            | Token::Dot(pos) | Token::DotDot(pos) | Token::DotDotEq(pos) | Token::Ellipsis(pos)
+            | Token::Comma(pos)
+            | Token::Question(pos) | Token::QuestionQuestion(pos) | Token::QuestionQuestionEq(pos)
+            | Token::QuestionDot(pos)
+            | Token::Colon(pos) | Token::ColonColon(pos)
+            | Token::PipeForward(pos)
+            | Token::ArrowGt(pos)
+            | Token::LShiftSub(pos)
+            | Token::Eof(pos) => pos,
+        }
+    }
+
+    pub fn position_mut(&mut self) -> &mut Position {
+        match self {
+            Token::Identifier(pos, _) | Token::Keyword(pos, _) | Token::RawIdentifier(pos, _) => pos,
+            Token::NumLiteral(pos, _) | Token::StrLiteral(pos, _) => pos,
+            Token::TaggedStrLiteral(pos, _) | Token::Attribute(pos, _) | Token::Error(pos, _) => pos,
+            Token::PreprocessorDirective(pos, _) => pos,
+            Token::LParen(pos) | Token::RParen(pos) | Token::LBrace(pos) | Token::RBrace(pos) | Token::Arrow(pos) | Token::FatArrow(pos)
+            | Token::Eq(pos) | Token::EqEq(pos) | Token::Lt(pos) | Token::Gt(pos) | Token::LtEq(pos) | Token::GtEq(pos)
+            | Token::AddEq(pos) | Token::SubEq(pos) | Token::MulEq(pos) | Token::DivEq(pos) | Token::ModEq(pos) | Token::RShiftEq(pos)
+            | Token::LShiftEq(pos) | Token::RShift(pos) | Token::LShift(pos) | Token::NotEq(pos) | Token::OrEq(pos) | Token::AndEq(pos)
+            | Token::XorEq(pos) | Token::Add(pos) | Token::Sub(pos) | Token::Mul(pos) | Token::Div(pos) | Token::Mod(pos)
+            | Token::AddAdd(pos) | Token::SubSub(pos) | Token::Not(pos) | Token::Xor(pos) | Token::Or(pos) | Token::OrOr(pos)
+            | Token::And(pos) | Token::AndAnd(pos) | Token::Semicolon(pos)
+            | Token::Dot(pos) | Token::DotDot(pos) | Token::DotDotEq(pos) | Token::Ellipsis(pos)
+            | Token::Comma(pos)
+            | Token::Question(pos) | Token::QuestionQuestion(pos) | Token::QuestionQuestionEq(pos)
+            | Token::QuestionDot(pos)
+            | Token::Colon(pos) | Token::ColonColon(pos)
+            | Token::PipeForward(pos)
+            | Token::ArrowGt(pos)
+            | Token::LShiftSub(pos)
+            | Token::Eof(pos) => pos,
+        }
+    }
+}
+
+impl PartialEq<TokenKind> for Token {
+    fn eq(&self, other: &TokenKind) -> bool {
+        self.kind() == *other
+    }
+}
+
+// one bit per TokenKind, so TokenKind must stay under 128 variants
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct TokenKindSet(u128);
+
+impl TokenKindSet {
+    pub fn new() -> TokenKindSet {
+        TokenKindSet(0)
+    }
+
+    pub fn insert(&mut self, kind: TokenKind) {
+        self.0 |= 1 << kind as u32;
+    }
+
+    pub fn contains(&self, kind: TokenKind) -> bool {
+        self.0 & (1 << kind as u32) != 0
+    }
+}
+
+impl<const N: usize> From<[TokenKind; N]> for TokenKindSet {
+    fn from(kinds: [TokenKind; N]) -> TokenKindSet {
+        kinds.into_iter().collect()
+    }
+}
+
+impl FromIterator<TokenKind> for TokenKindSet {
+    fn from_iter<I: IntoIterator<Item = TokenKind>>(kinds: I) -> TokenKindSet {
+        let mut set = TokenKindSet::new();
+        kinds.into_iter().for_each(|kind| set.insert(kind));
+        set
+    }
+}
+
+// single-char operators and delimiters only; Semicolon is left out because its char is configurable
+impl TryFrom<&Token> for char {
+    type Error = TokenKind;
+
+    fn try_from(token: &Token) -> Result<char, TokenKind> {
+        match token {
+            Token::LParen(_) => Ok('('),
+            Token::RParen(_) => Ok(')'),
+            Token::LBrace(_) => Ok('{'),
+            Token::RBrace(_) => Ok('}'),
+            Token::Comma(_)  => Ok(','),
+            Token::Colon(_)  => Ok(':'),
+            Token::Dot(_)    => Ok('.'),
+            Token::Eq(_)     => Ok('='),
+            Token::Lt(_)     => Ok('<'),
+            Token::Gt(_)     => Ok('>'),
+            Token::Add(_)    => Ok('+'),
+            Token::Sub(_)    => Ok('-'),
+            Token::Mul(_)    => Ok('*'),
+            Token::Div(_)    => Ok('/'),
+            Token::Mod(_)    => Ok('%'),
+            Token::Not(_)    => Ok('!'),
+            Token::Xor(_)    => Ok('^'),
+            Token::Or(_)     => Ok('|'),
+            Token::And(_)    => Ok('&'),
+            Token::Question(_) => Ok('?'),
+            _ => Err(token.kind()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum LexErrorKind {
+    UnknownChar(char),
+    UnterminatedString,
+    UnterminatedBlockComment,
+    IdentifierTooLong,
+    InvalidRawIdentifier,
+    UnterminatedBacktickIdentifier,
+    EmptyBacktickIdentifier,
+    InvalidAttributeName,
+    UnbalancedAttribute,
+    DisallowedOperator(TokenKind),
+    BudgetExhausted,
+    InvalidNumberSuffix,
+    MixedIndentation,
+    TooManyErrors,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, PartialOrd, Ord)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct LexError {
+    pub position: Position,
+    pub kind: LexErrorKind,
+}
+
+impl LexError {
+    fn new(position: Position, kind: LexErrorKind) -> Self {
+        Self { position, kind }
+    }
+
+    // rustc-style snippet: the offending line with a caret under the error's column.
+    // Tabs before the column are copied into the caret line so it lines up at any tab width
+    pub fn render(&self, source: &str) -> String {
+        let (row, col) = self.position.start;
+        // the line is found from the byte offset, which doesn't depend on tab_width, start or zero_based
+        let at = self.position.start_byte.min(source.len());
+        let line_start = source[..at].rfind('\n').map_or(0, |i| i + 1);
+        let line = source[line_start..].lines().next().unwrap_or("");
+        let pad: String = source[line_start..at].chars()
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        let gutter = " ".repeat(row.to_string().len());
+        format!("error[{}]: {} at {row}:{col}\n{gutter} |\n{row} | {line}\n{gutter} | {pad}^\n", self.kind.code(), self.kind)
+    }
+
+    // the ASCII the user probably meant for a look-alike Unicode char, e.g. `"` for `“`
+    pub fn suggestion(&self) -> Option<String> {
+        let LexErrorKind::UnknownChar(c) = self.kind else { return None };
+        CONFUSABLE_CHARS.iter().find(|(found, _)| *found == c).map(|(_, meant)| meant.to_string())
+    }
+}
+
+impl LexErrorKind {
+    // stable across releases, for filtering and suppression lists; new kinds take the next free code
+    pub fn code(&self) -> &'static str {
+        match self {
+            LexErrorKind::UnknownChar(_) => "E0001",
+            LexErrorKind::UnterminatedString => "E0002",
+            LexErrorKind::UnterminatedBlockComment => "E0003",
+            LexErrorKind::IdentifierTooLong => "E0004",
+            LexErrorKind::InvalidRawIdentifier => "E0005",
+            LexErrorKind::UnterminatedBacktickIdentifier => "E0006",
+            LexErrorKind::EmptyBacktickIdentifier => "E0007",
+            LexErrorKind::InvalidAttributeName => "E0008",
+            LexErrorKind::UnbalancedAttribute => "E0009",
+            LexErrorKind::DisallowedOperator(_) => "E0010",
+            LexErrorKind::BudgetExhausted => "E0011",
+            LexErrorKind::InvalidNumberSuffix => "E0012",
+            LexErrorKind::MixedIndentation => "E0013",
+            LexErrorKind::TooManyErrors => "E0014",
+        }
+    }
+
+    // warnings still leave a usable token stream; callers decide whether they fail a build
+    pub fn severity(&self) -> Severity {
+        match self {
+            LexErrorKind::MixedIndentation => Severity::Warning,
+            _ => Severity::Error,
+        }
+    }
+}
+
+impl std::fmt::Display for LexErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LexErrorKind::UnknownChar(c) => write!(f, "unknown char `{c}`"),
+            LexErrorKind::UnterminatedString => write!(f, "unterminated string literal"),
+            LexErrorKind::UnterminatedBlockComment => write!(f, "unterminated block comment"),
+            LexErrorKind::IdentifierTooLong => write!(f, "identifier too long"),
+            LexErrorKind::InvalidRawIdentifier => write!(f, "invalid raw identifier"),
+            LexErrorKind::UnterminatedBacktickIdentifier => write!(f, "unterminated backtick identifier"),
+            LexErrorKind::EmptyBacktickIdentifier => write!(f, "empty backtick identifier"),
+            LexErrorKind::InvalidAttributeName => write!(f, "invalid attribute name"),
+            LexErrorKind::UnbalancedAttribute => write!(f, "unbalanced attribute arguments"),
+            LexErrorKind::DisallowedOperator(kind) => write!(f, "disallowed operator {kind:?}"),
+            LexErrorKind::BudgetExhausted => write!(f, "work budget exhausted"),
+            LexErrorKind::InvalidNumberSuffix => write!(f, "invalid number suffix"),
+            LexErrorKind::MixedIndentation => write!(f, "mixed tabs and spaces in indentation"),
+            LexErrorKind::TooManyErrors => write!(f, "too many errors, giving up"),
+        }
+    }
+}
+
+impl std::fmt::Display for LexError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (row, col) = self.position.start;
+        write!(f, "[{}] {} at {row}:{col}", self.kind.code(), self.kind)
+    }
+}
+
+impl std::error::Error for LexError {}
+
+// the label spans are byte offsets into the lexed source, so reports need that source attached
+#[cfg(feature = "diagnostics")]
+impl miette::Diagnostic for LexError {
+    fn code<'a>(&'a self) -> Option<Box<dyn std::fmt::Display + 'a>> {
+        Some(Box::new(self.kind.code()))
+    }
+
+    fn severity(&self) -> Option<miette::Severity> {
+        Some(match self.kind.severity() {
+            Severity::Warning => miette::Severity::Warning,
+            Severity::Error => miette::Severity::Error,
+        })
+    }
+
+    fn labels(&self) -> Option<Box<dyn Iterator<Item = miette::LabeledSpan> + '_>> {
+        Some(Box::new(std::iter::once(miette::LabeledSpan::at(self.position.byte_range(), self.kind.to_string()))))
+    }
+
+    fn help<'a>(&'a self) -> Option<Box<dyn std::fmt::Display + 'a>> {
+        self.suggestion().map(|meant| Box::new(format!("did you mean `{meant}`?")) as Box<dyn std::fmt::Display>)
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum LexWarning {
+    ConfusableOperator { found: String, suggestion: String, position: Position },
+    LineTooLong { row: usize, length: usize },
+    MixedLineEndings,
+}
+
+// Unicode punctuation that is easily pasted in place of the ASCII token it resembles
+const CONFUSABLE_CHARS: &[(char, &str)] = &[
+    ('\u{201c}', "\""), // “
+    ('\u{201d}', "\""), // ”
+    ('\u{2013}', "-"),  // –
+    ('\u{2014}', "-"),  // —
+    ('\u{2212}', "-"),  // −
+    ('\u{ff1b}', ";"),  // ；
+    ('\u{ff0c}', ","),  // ，
+    ('\u{ff08}', "("),  // （
+    ('\u{ff09}', ")"),  // ）
+    ('\u{ff1d}', "="),  // ＝
+];
+
+// adjacent token pairs that are most likely a typo of a compound operator
+const CONFUSABLE_OPERATORS: &[(TokenKind, TokenKind, &str, &str)] = &[
+    (TokenKind::Eq, TokenKind::Lt,  "=<", "<="),
+    (TokenKind::Eq, TokenKind::Not, "=!", "!="),
+];
+
+pub fn lint_confusables(tokens: &[Token]) -> Vec<LexWarning> {
+    tokens.windows(2).filter_map(|pair| {
+        let (first, second) = (pair[0].position(), pair[1].position());
+        if first.end.0 != second.start.0 || first.end.1.checked_add(1) != Some(second.start.1) { return None; }
+
+        let (_, _, found, suggestion) = CONFUSABLE_OPERATORS.iter()
+            .find(|(a, b, _, _)| pair[0] == *a && pair[1] == *b)?;
+        Some(LexWarning::ConfusableOperator {
+            found: found.to_string(),
+            suggestion: suggestion.to_string(),
+            position: first.merge(second),
+        })
+    }).collect()
+}
+
+// spans of consecutive repeats of a flagged kind, like `;;` or `,,`. Whitespace between them doesn't matter,
+// so a caller that only wants touching pairs can drop spans wider than two columns
+pub fn find_duplicate_adjacent(tokens: &[Token], kinds: &TokenKindSet) -> Vec<Position> {
+    tokens.windows(2)
+        .filter(|pair| pair[0].kind() == pair[1].kind() && kinds.contains(pair[0].kind()))
+        .map(|pair| pair[0].position().merge(pair[1].position()))
+        .collect()
+}
+
+#[derive(Debug, Clone)]
+pub struct LexerOptions {
+    pub literal_prefixes: HashSet<String>, // identifiers that tag a directly following string literal
+    pub keywords: HashSet<String>,         // identifiers lexed as Keyword instead
+    pub raw_identifiers: bool,             // `r#name` lexes as RawIdentifier, even if `name` is a keyword
+    pub max_identifier_len: Option<usize>, // longer identifiers are an error
+    pub statement_terminator: Option<char>, // char lexed as Semicolon, None for dialects without one
+    pub dash_dash_comment: bool,           // `--` starts a line comment instead of lexing as SubSub
+    pub start: Option<(usize, usize)>,     // (row, col) of the first char, for snippets of a larger document
+    pub attributes: bool,                  // `@name` and `@name(args)` lex as Attribute, otherwise `@` is unknown
+    pub max_line_length: Option<usize>,    // longer lines get a LineTooLong warning from tokenize_with_warnings
+    pub disallowed_ops: TokenKindSet,      // operators that are an error, for restricted dialects
+    pub backtick_identifiers: bool,        // `my col` lexes as Identifier("my col"); reserves backticks, so no backtick strings
+    pub unicode_whitespace: bool,          // skip any char::is_whitespace char, not just space, tab and newline
+    pub detect_mixed_line_endings: bool,   // warn once from tokenize_with_warnings if both \n and \r\n are used
+    pub work_budget: Option<usize>,        // max chars read (lookahead included) before giving up, checked as each is read
+    pub strict_number_suffix: bool,        // digits running into identifier chars (`1abc`) are an error instead of two tokens
+    pub preprocessor_directives: bool,     // a `#word` line lexes whole as PreprocessorDirective
+    pub max_errors: Option<usize>,         // tokenize_all gives up with TooManyErrors after this many
+    pub unicode_digits: bool,              // non-ASCII decimal digits (e.g. Arabic-Indic) lex as numbers, stored as ASCII
+    pub nested_block_comments: bool,       // `/*` inside a block comment opens a nested one, as in Rust
+    pub tab_width: usize,                  // a tab moves to the next tab stop, one every this many columns
+    pub source_id: u32,                    // stamped on every position, to tell apart files lexed separately
+    pub pipe_forward: bool,                // `|>` lexes as PipeForward rather than `|` then `>`
+    pub zero_based: bool,                  // rows and cols count from 0, as in LSP, and so does `start`
+    pub pipeline_arrows: bool,             // `->>` and `<<-` lex as ArrowGt and LShiftSub
+}
+
+// the defaults are what plain `tokenize` uses:
+// - no literal prefixes, keywords or raw identifiers
+// - no identifier length limit
+// - `;` as the statement terminator
+// - `--` lexes as SubSub
+// - counting starts at the first row and col
+// - `@` and backticks are unknown chars
+// - only ' ', '\t', '\r' and '\n' are whitespace
+// - no line ending checks
+// - no work budget
+// - no line length limit
+// - every operator allowed
+// - `1abc` lexes as NumLiteral then Identifier
+// - `#` is an unknown char outside raw identifiers
+// - tokenize_all stops after 100 errors
+// - only ASCII digits start numbers
+// - block comments end at the first `*/`, as in C
+// - a tab between tokens is one column wide
+// - positions are in source 0
+// - `|>` is `|` followed by `>`
+// - rows and cols count from 1
+// - `->>` is `->` then `>`, and `<<-` is `<<` then `-`
+// override single fields with `LexerOptions { keywords, ..Default::default() }`
+impl Default for LexerOptions {
+    fn default() -> LexerOptions {
+        LexerOptions {
+            literal_prefixes: HashSet::new(),
+            keywords: HashSet::new(),
+            raw_identifiers: false,
+            max_identifier_len: None,
+            statement_terminator: Some(';'),
+            dash_dash_comment: false,
+            start: None,
+            attributes: false,
+            max_line_length: None,
+            disallowed_ops: TokenKindSet::new(),
+            backtick_identifiers: false,
+            unicode_whitespace: false,
+            detect_mixed_line_endings: false,
+            work_budget: None,
+            strict_number_suffix: false,
+            preprocessor_directives: false,
+            max_errors: Some(100),
+            unicode_digits: false,
+            nested_block_comments: false,
+            tab_width: 1,
+            source_id: 0,
+            pipe_forward: false,
+            zero_based: false,
+            pipeline_arrows: false,
+        }
+    }
+}
+
+impl LexerOptions {
+    // `start`, or the first row and col of whichever base zero_based picks
+    fn first_position(&self) -> (usize, usize) {
+        let first = usize::from(!self.zero_based);
+        self.start.unwrap_or((first, first))
+    }
+
+    fn columns(&self) -> Columns {
+        Columns { first: usize::from(!self.zero_based), tab_width: self.tab_width.max(1) }
+    }
+}
+
+macro_rules! next_and {
+    ($iter:ident, $col:ident, $ret:ident) => {
+        {
+            $iter.next();
+            $col = $col.saturating_add(1);
+            $ret
+        }
+    };
+
+    ($iter:ident, $col:ident, $ret:expr) => {
+        {
+            let ret = $ret;
+            $iter.next();
+            $col = $col.saturating_add(1);
+            ret
+        }
+    };
+}
+
+// positions are inclusive and tokens never overlap, so at most one token holds the cursor;
+// the stream is ordered by start, which lets this binary search for the last token starting at or before it
+pub fn token_at(tokens: &[Token], row: usize, col: usize) -> Option<&Token> {
+    let idx = tokens.partition_point(|t| t.position().start <= (row, col));
+    let token = tokens.get(idx.checked_sub(1)?)?;
+    if token.position().contains(row, col) { Some(token) } else { None }
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum IncDecRole {
+    Prefix,
+    Postfix,
+    Error, // not a ++ or --
+}
+
+// the lexer stays context free, so whether ++/-- applies to the operand before or after it is
+// decided here from the previous token: right after an operand it's postfix, anywhere else prefix
+pub fn disambiguate_incdec(prev: Option<&Token>, tok: &Token) -> IncDecRole {
+    if !matches!(tok, Token::AddAdd(_) | Token::SubSub(_)) {
+        return IncDecRole::Error;
+    }
+
+    match prev {
+        Some(Token::Identifier(..) | Token::RawIdentifier(..) | Token::NumLiteral(..) | Token::StrLiteral(..)
+            | Token::TaggedStrLiteral(..) | Token::RParen(_)) => IncDecRole::Postfix,
+        _ => IncDecRole::Prefix,
+    }
+}
+
+// `x: Int` ascribes a type when the Colon directly follows a name; `::` is always a path separator,
+// so `x :: Int` is never an ascription, while `x:Int` and `x : Int` are
+pub fn is_type_ascription(prev: Option<&Token>, tok: &Token) -> bool {
+    matches!(tok, Token::Colon(_)) && matches!(prev, Some(Token::Identifier(..) | Token::RawIdentifier(..)))
+}
+
+// the source text covered by a run of tokens, from the first token's start to the last token's end
+pub fn slice_tokens<'a>(source: &'a str, tokens: &[Token]) -> &'a str {
+    let (Some(first), Some(last)) = (tokens.first(), tokens.last()) else { return "" };
+    &source[first.position().start_byte..last.position().end_byte]
+}
+
+// pairs every token with the kind of the token after it (None for the trailing Eof),
+// so LL(1) decisions don't need a separate lookahead
+pub fn with_following_kinds(tokens: Vec<Token>) -> Vec<(Token, Option<TokenKind>)> {
+    let following: Vec<_> = tokens.iter().skip(1).map(|t| Some(t.kind())).chain([None]).collect();
+    tokens.into_iter().zip(following).collect()
+}
+
+pub fn histogram(tokens: &[Token]) -> HashMap<TokenKind, usize> {
+    let mut counts = HashMap::new();
+    for token in tokens {
+        *counts.entry(token.kind()).or_insert(0) += 1;
+    }
+    counts
+}
+
+// maps positions in a generated file back to the tokens they came from, for a single source file
+#[derive(Debug, Clone, Default)]
+pub struct SourceMap {
+    mappings: Vec<((usize, usize), (usize, usize))>, // (generated, original), both (row, col)
+    zero_based: bool,                                // both count from 0, see LexerOptions::zero_based
+}
+
+impl SourceMap {
+    pub fn new() -> SourceMap {
+        SourceMap::default()
+    }
+
+    // for tokens lexed with LexerOptions::zero_based; generated positions are then 0-based too
+    pub fn zero_based() -> SourceMap {
+        SourceMap { zero_based: true, ..SourceMap::default() }
+    }
+
+    pub fn add(&mut self, generated: (usize, usize), original: &Token) {
+        self.mappings.push((generated, original.position().start));
+    }
+
+    // the Source Map v3 `mappings` field: one `;`-separated group per generated line,
+    // each segment a VLQ of [generated col, source index, original row, original col]
+    pub fn mappings(&self) -> String {
+        let mut mappings = self.mappings.clone();
+        mappings.sort();
+
+        let mut out = String::new();
+        let base = usize::from(!self.zero_based);
+        let mut line = base;
+        let (mut prev_col, mut prev_orig) = (0, (0, 0));
+        for (i, ((row, col), (orig_row, orig_col))) in mappings.into_iter().enumerate() {
+            if row > line {
+                out.extend(std::iter::repeat_n(';', row - line));
+                line = row;
+                prev_col = 0;
+            } else if i > 0 {
+                out.push(',');
+            }
+
+            // v3 coordinates are 0-based
+            let from_base = |n: usize| n as i64 - base as i64;
+            let (col, orig) = (from_base(col), (from_base(orig_row), from_base(orig_col)));
+            for value in [col - prev_col, 0, orig.0 - prev_orig.0, orig.1 - prev_orig.1] {
+                encode_vlq(value, &mut out);
+            }
+            (prev_col, prev_orig) = (col, orig);
+        }
+
+        out
+    }
+}
+
+fn encode_vlq(value: i64, out: &mut String) {
+    const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut vlq = (value.unsigned_abs() << 1) | u64::from(value < 0);
+    loop {
+        let digit = (vlq & 31) as usize;
+        vlq >>= 5;
+        if vlq == 0 {
+            out.push(BASE64[digit] as char);
+            break;
+        }
+        out.push(BASE64[digit | 32] as char);
+    }
+}
+
+// the lexer's input: a peekable Chars that also knows the byte offset it has read up to.
+// `steps` counts every char read, lookahead on clones included, and reading stops once it's past `budget`.
+// `lines` gets the (start byte, length in chars) of every line as its newline is read, whichever
+// arm reads it; clones may read a line again after a rewind, so each is kept once
+#[derive(Clone)]
+struct Source<'a> {
+    chars: std::str::Chars<'a>,
+    peeked: Option<Option<char>>,
+    len: usize,
+    budget: Option<usize>,
+    steps: &'a Cell<usize>,
+    lines: &'a RefCell<Vec<(usize, usize)>>,
+    line_start: usize,
+    line_chars: usize,
+    cr: bool, // the last char read was `\r`, which isn't counted if a `\n` follows
+}
+
+impl<'a> Source<'a> {
+    fn new(input: &'a str, budget: Option<usize>, steps: &'a Cell<usize>, lines: &'a RefCell<Vec<(usize, usize)>>) -> Source<'a> {
+        Source { chars: input.chars(), peeked: None, len: input.len(), budget, steps, lines, line_start: 0, line_chars: 0, cr: false }
+    }
+
+    fn exhausted(&self) -> bool {
+        self.budget.is_some_and(|budget| self.steps.get() > budget)
+    }
+
+    fn read(&mut self) -> Option<char> {
+        if self.exhausted() {
+            return None;
+        }
+        let next = self.chars.next()?;
+        self.steps.set(self.steps.get() + 1);
+        if self.exhausted() {
+            return None;
+        }
+        if next == '\n' {
+            self.end_line(self.line_chars - usize::from(self.cr));
+            (self.line_start, self.line_chars) = (self.len - self.chars.as_str().len(), 0);
+        } else {
+            self.line_chars += 1;
+        }
+        self.cr = next == '\r';
+        Some(next)
+    }
+
+    fn end_line(&self, length: usize) {
+        let mut lines = self.lines.borrow_mut();
+        if let Err(i) = lines.binary_search_by_key(&self.line_start, |&(start, _)| start) {
+            lines.insert(i, (self.line_start, length));
+        }
+    }
+
+    fn peek(&mut self) -> Option<&char> {
+        if self.peeked.is_none() {
+            self.peeked = Some(self.read());
+        }
+        self.peeked.as_ref().and_then(Option::as_ref)
+    }
+
+    fn next_if(&mut self, accept: impl FnOnce(&char) -> bool) -> Option<char> {
+        match self.peek() {
+            Some(next) if accept(next) => self.next(),
+            _ => None,
+        }
+    }
+
+    // byte offset of the next char that next() will return
+    fn offset(&self) -> usize {
+        let pending = self.peeked.flatten().map_or(0, char::len_utf8);
+        self.len - self.chars.as_str().len() - pending
+    }
+}
+
+impl Iterator for Source<'_> {
+    type Item = char;
+
+    fn next(&mut self) -> Option<char> {
+        match self.peeked.take() {
+            Some(peeked) => peeked,
+            None => self.read(),
+        }
+    }
+}
+
+// attribute arguments are lexed on their own, so their byte offsets start at the argument list
+fn shift_token_bytes(tokens: &mut [Token], by: usize) {
+    for token in tokens {
+        if let Token::Attribute(_, attribute) = token {
+            shift_token_bytes(&mut attribute.1, by);
+        }
+        let pos = token.position_mut();
+        (pos.start_byte, pos.end_byte) = (pos.start_byte + by, pos.end_byte + by);
+    }
+}
+
+// the zero of each non-ASCII decimal digit run accepted with LexerOptions::unicode_digits
+const UNICODE_DIGIT_ZEROS: &[char] = &[
+    '\u{660}',  // Arabic-Indic
+    '\u{6f0}',  // Extended Arabic-Indic
+    '\u{966}',  // Devanagari
+    '\u{9e6}',  // Bengali
+    '\u{e50}',  // Thai
+    '\u{ff10}', // fullwidth
+];
+
+fn ascii_digit(c: char, unicode_digits: bool) -> Option<char> {
+    if c.is_ascii_digit() { return Some(c); }
+    if !unicode_digits { return None; }
+    let value = UNICODE_DIGIT_ZEROS.iter().find_map(|&zero| (c as u32).checked_sub(zero as u32).filter(|value| *value < 10))?;
+    char::from_digit(value, 10)
+}
+
+// how columns are counted: the first one on each line, and tab stops every `tab_width` from there
+#[derive(Clone, Copy)]
+struct Columns {
+    first: usize,
+    tab_width: usize,
+}
+
+impl Columns {
+    // the last column covered by a tab at `col`
+    fn tab_end(self, col: usize) -> usize {
+        (col.saturating_sub(self.first) / self.tab_width * self.tab_width).saturating_add(self.tab_width - 1).saturating_add(self.first)
+    }
+
+    // where the char after `c` at `(row, col)` goes
+    fn after(self, (row, col): (usize, usize), c: char) -> (usize, usize) {
+        match c {
+            '\n' => (row.saturating_add(1), self.first),
+            '\t' => (row, self.tab_end(col).saturating_add(1)),
+            _ => (row, col.saturating_add(1)),
+        }
+    }
+}
+
+// consumes a string body up to and including the closing quote, None if unterminated;
+// strings may span lines and keep their newlines, so the body moves `row` as well
+fn lex_string(chars: &mut Source, columns: Columns, row: &mut usize, col: &mut usize) -> Option<String> {
+    let (mut val, mut prev) = (String::new(), '"');
+    while let Some(&n) = chars.peek() {
+        chars.next();
+        (*row, *col) = columns.after((*row, *col), prev);
+        prev = n;
+
+        if n == '"' {
+            return Some(val);
+        }
+
+        val.push(n);
+    }
+
+    None
+}
+
+pub fn tokenize(input: &str) -> Result<Vec<Token>, LexError> {
+    tokenize_with_options(input, &LexerOptions::default())
+}
+
+// for untrusted input: bounds the chars the lexer reads rather than the input size, so lookahead counts too
+pub fn tokenize_with_budget(input: &str, work_budget: usize) -> Result<Vec<Token>, LexError> {
+    tokenize_with_options(input, &LexerOptions { work_budget: Some(work_budget), ..Default::default() })
+}
+
+pub fn tokenize_with_index(input: &str) -> Result<(Vec<Token>, LineIndex<'_>), LexError> {
+    let mut tokens = vec![];
+    let lexed = lex_to_eof(input, &LexerOptions::default(), &mut tokens)?;
+    Ok((tokens, LineIndex::from_lines(input, &lexed.lines)))
+}
+
+// line lengths are counted in chars, excluding the line ending
+pub fn tokenize_with_warnings(input: &str, options: &LexerOptions) -> Result<(Vec<Token>, Vec<LexWarning>), LexError> {
+    let mut tokens = vec![];
+    let lexed = lex_to_eof(input, options, &mut tokens)?;
+    let mut warnings = vec![];
+    if let Some(max) = options.max_line_length {
+        for (i, &(_, length)) in lexed.lines.iter().enumerate() {
+            if length > max {
+                warnings.push(LexWarning::LineTooLong { row: options.first_position().0.saturating_add(i), length });
+            }
+        }
+    }
+
+    if options.detect_mixed_line_endings {
+        let crlf = input.matches("\r\n").count();
+        if crlf > 0 && crlf < input.matches('\n').count() {
+            warnings.push(LexWarning::MixedLineEndings);
+        }
+    }
+
+    Ok((tokens, warnings))
+}
+
+// for multi-file compilers: every position records `source_id`, so spans from different files stay distinct
+pub fn tokenize_with_source(input: &str, source_id: u32) -> Result<Vec<Token>, LexError> {
+    tokenize_with_options(input, &LexerOptions { source_id, ..Default::default() })
+}
+
+pub fn tokenize_with_options(input: &str, options: &LexerOptions) -> Result<Vec<Token>, LexError> {
+    let mut output = vec![];
+    lex_to_eof(input, options, &mut output)?;
+    Ok(output)
+}
+
+// the token array lives in `bump` and is freed with it; token text and attribute arguments still use the global heap
+#[cfg(feature = "arena")]
+pub fn tokenize_in<'bump>(input: &str, options: &LexerOptions, bump: &'bump bumpalo::Bump) -> Result<bumpalo::collections::Vec<'bump, Token>, LexError> {
+    let mut output = bumpalo::collections::Vec::new_in(bump);
+    lex_to_eof(input, options, &mut output)?;
+    Ok(output)
+}
+
+fn lex_to_eof(input: &str, options: &LexerOptions, output: &mut impl TokenOutput) -> Result<Lexed, LexError> {
+    let lexed = lex(input, options, output, None, true)
+        .map_err(|err| LexError { position: err.position.in_source(options.source_id), ..err })?;
+    output.push(Token::Eof(Position::new(lexed.end, lexed.end).with_bytes(input.len(), input.len()).in_source(options.source_id)));
+    Ok(lexed)
+}
+
+// keeps going past unknown chars and unterminated strings, leaving a Token::Error in their place;
+// any other error still ends the scan early. Warnings are reported alongside errors, in source order
+pub fn tokenize_all(input: &str) -> (Vec<Token>, Vec<LexError>) {
+    tokenize_all_with_options(input, &LexerOptions::default())
+}
+
+pub fn tokenize_all_with_options(input: &str, options: &LexerOptions) -> (Vec<Token>, Vec<LexError>) {
+    let mut output = vec![];
+    let mut errors = vec![];
+    let eof = match lex(input, options, &mut output, Some(&mut errors), true) {
+        Ok(lexed) => Position::new(lexed.end, lexed.end).with_bytes(input.len(), input.len()),
+        Err(err) => {
+            let eof = Position::new(err.position.start, err.position.start).with_bytes(err.position.start_byte, err.position.start_byte);
+            errors.push(err);
+            eof
+        },
+    };
+
+    output.push(Token::Eof(eof.in_source(options.source_id)));
+    for err in &mut errors {
+        err.position = err.position.clone().in_source(options.source_id);
+    }
+    errors.sort_by_key(|err| err.position.start);
+    (output, errors)
+}
+
+// where lex puts tokens, so they can go to an arena as well as the global heap
+trait TokenOutput: std::ops::DerefMut<Target = [Token]> {
+    fn push(&mut self, token: Token);
+}
+
+impl TokenOutput for Vec<Token> {
+    fn push(&mut self, token: Token) {
+        Vec::push(self, token)
+    }
+}
+
+#[cfg(feature = "arena")]
+impl TokenOutput for bumpalo::collections::Vec<'_, Token> {
+    fn push(&mut self, token: Token) {
+        bumpalo::collections::Vec::push(self, token)
+    }
+}
+
+// what lex found out about the input besides its tokens
+struct Lexed {
+    end: (usize, usize),         // where the input ended
+    lines: Vec<(usize, usize)>,  // (start byte, length in chars without the line ending) of every line
+    steps: usize,                // chars read, for the caller's work budget
+}
+
+// recoverable errors and warnings go to `errors` when given.
+// `line_start` is false when `input` continues a line, so its leading whitespace isn't indentation
+fn lex(input: &str, options: &LexerOptions, output: &mut impl TokenOutput, errors: Option<&mut Vec<LexError>>, line_start: bool) -> Result<Lexed, LexError> {
+    let (steps, lines) = (Cell::new(0), RefCell::new(vec![]));
+    let chars = Source::new(input, options.work_budget, &steps, &lines);
+    // a scan the budget cut short fails however it was cut short, but it's the budget that stopped it
+    let end = lex_source(chars, options, output, errors, line_start).map_err(|err| {
+        if options.work_budget.is_some_and(|budget| steps.get() > budget) { LexError { kind: LexErrorKind::BudgetExhausted, ..err } } else { err }
+    })?;
+    Ok(Lexed { end, lines: lines.into_inner(), steps: steps.get() })
+}
+
+fn lex_source(mut chars: Source, options: &LexerOptions, output: &mut impl TokenOutput, mut errors: Option<&mut Vec<LexError>>, line_start: bool) -> Result<(usize, usize), LexError> {
+    // rows and cols saturate, since `start` may be anywhere; byte offsets can't outgrow the input
+    let columns = options.columns();
+    let (mut row, mut col) = options.first_position();
+    // the start of the current line while still in its indentation, and whether spaces and tabs were seen
+    let new_line = |(row, col), byte: usize| Some((Position::new((row, col), (row, col)).with_bytes(byte, byte + 1), false, false));
+    let mut indent = if line_start { new_line((row, col), 0) } else { None };
+    // only whitespace so far on the current line, which is where directives may start
+    let mut blank_line = line_start;
+    let reported = errors.as_ref().map_or(0, |errors| errors.len());
+    let (mut lexed, mut start_byte) = (output.len(), 0);
+    loop {
+        // whatever the previous char started is fully read now, so its byte span is known
+        let offset = chars.offset();
+        for token in &mut output[lexed..] {
+            let pos = token.position_mut();
+            (pos.start_byte, pos.end_byte, pos.source_id) = (start_byte, offset, options.source_id);
+        }
+        (lexed, start_byte) = (output.len(), offset);
+        let Some(char) = chars.next() else { break };
+        let seen = errors.as_ref().map_or(0, |errors| errors.len());
+        // errors point at the char that starts the current token
+        let here = Position::new((row, col), (row, col)).with_bytes(start_byte, start_byte + char.len_utf8());
+
+        if !matches!(char, ' ' | '\t') {
+            indent = None;
+        }
+        let at_line_start = blank_line;
+        if !char.is_whitespace() {
+            blank_line = false;
+        }
+
+        match char {
+            c if Some(c) == options.statement_terminator => {
+                output.push(Token::Semicolon(Position::new((row, col), (row, col))));
+                if c == '\n' {
+                    (row, col) = columns.after((row, col), c);
+                    indent = new_line((row, col), chars.offset());
+                    blank_line = true;
+                }
+            },
+            ' ' | '\n' | '\t' | '\r' => {
+                if let Some((at, spaces, tabs)) = &mut indent {
+                    (*spaces, *tabs) = (*spaces || char == ' ', *tabs || char == '\t');
+                    if *spaces && *tabs {
+                        if let Some(errors) = errors.as_deref_mut() {
+                            errors.push(LexError::new(at.clone(), LexErrorKind::MixedIndentation));
+                        }
+                        indent = None;
+                    }
+                }
+
+                if char == '\n' {
+                    (row, col) = columns.after((row, col), char);
+                    indent = new_line((row, col), chars.offset());
+                    blank_line = true;
+                } else if char == '\t' {
+                    col = columns.tab_end(col);
+                }
+            },
+            c if options.unicode_whitespace && c.is_whitespace() => {},
+            c if c.is_ascii_alphabetic() || c == '_' => {
+                let start = (row, col);
+                let len = 1 + chars.clone().take_while(|n| n.is_ascii_alphanumeric() || *n == '_').count();
+                if options.max_identifier_len.is_some_and(|max| len > max) {
+                    return Err(LexError::new(here.clone(), LexErrorKind::IdentifierTooLong));
+                }
+
+                let mut val = String::with_capacity(len);
+                val.push(char);
+                while let Some(n) = chars.peek() {
+                    if !n.is_ascii_alphanumeric() && *n != '_' { break; }
+                    col = col.saturating_add(1);
+                    val.push(*n);
+                    chars.next();
+                }
+
+                if options.raw_identifiers && val == "r" && chars.peek() == Some(&'#') {
+                    chars.next();
+                    col = col.saturating_add(1);
+                    let mut name = String::new();
+                    while let Some(n) = chars.peek() {
+                        if !n.is_ascii_alphanumeric() && *n != '_' { break; }
+                        col = col.saturating_add(1);
+                        name.push(*n);
+                        chars.next();
+                    }
+
+                    if !name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+                        return Err(LexError::new(here.clone(), LexErrorKind::InvalidRawIdentifier));
+                    }
+
+                    output.push(Token::RawIdentifier(Position::new(start, (row, col)), name));
+                    col = col.saturating_add(1);
+                    continue;
+                }
+
+                if chars.peek() == Some(&'"') && options.literal_prefixes.contains(&val) {
+                    let before = (chars.clone(), row, col);
+                    chars.next();
+                    col = col.saturating_add(1);
+                    let (quote, quote_byte) = ((row, col), chars.offset() - 1);
+                    if let Some(lit) = lex_string(&mut chars, columns, &mut row, &mut col) {
+                        output.push(Token::TaggedStrLiteral(Position::new(start, (row, col)), Box::new((val, lit))));
+                        col = col.saturating_add(1);
+                        continue;
+                    }
+
+                    if errors.is_none() {
+                        return Err(LexError::new(Position::new(quote, quote).with_bytes(quote_byte, quote_byte + 1), LexErrorKind::UnterminatedString));
+                    }
+
+                    // lex the tag as a plain identifier and leave the quote for the '"' arm to report
+                    (chars, row, col) = before;
+                }
+
+                if options.keywords.contains(&val) {
+                    output.push(Token::Keyword(Position::new(start, (row, col)), val));
+                } else {
+                    output.push(Token::Identifier(Position::new(start, (row, col)), val));
+                }
+            },
+            //TODO: decimals
+            c if let Some(digit) = ascii_digit(c, options.unicode_digits) => {
+                let start = (row, col);
+                let mut val = String::from(digit);
+                while let Some(digit) = chars.peek().and_then(|&n| ascii_digit(n, options.unicode_digits)) {
+                    col = col.saturating_add(1);
+                    val.push(digit);
+                    chars.next();
+                }
+
+                if options.strict_number_suffix && chars.peek().is_some_and(|n| n.is_ascii_alphabetic() || *n == '_') {
+                    return Err(LexError::new(here.clone(), LexErrorKind::InvalidNumberSuffix));
+                }
+
+                output.push(Token::NumLiteral(Position::new(start, (row, col)), val));
+            },
+            '"' => {
+                let start = (row, col);
+                let rest = chars.clone();
+                if let Some(val) = lex_string(&mut chars, columns, &mut row, &mut col) {
+                    output.push(Token::StrLiteral(Position::new(start, (row, col)), val));
+                } else {
+                    let err = LexError::new(here.clone(), LexErrorKind::UnterminatedString);
+                    // no use recovering when it was the budget that ended the string
+                    let Some(errors) = errors.as_deref_mut().filter(|_| !chars.exhausted()) else { return Err(err) };
+                    errors.push(err);
+                    output.push(Token::Error(Position::new(start, start), char.to_string()));
+                    (chars, row, col) = (rest, start.0, start.1);
+                }
+            },
+            '#' if options.preprocessor_directives
+                && at_line_start
+                && chars.peek().is_some_and(char::is_ascii_alphabetic) => {
+                let start = (row, col);
+                let mut line = String::from(char);
+                while let Some(n) = chars.next_if(|n| *n != '\n') {
+                    col = col.saturating_add(1);
+                    line.push(n);
+                }
+
+                if line.ends_with('\r') {
+                    line.pop();
+                }
+
+                output.push(Token::PreprocessorDirective(Position::new(start, (row, start.1.saturating_add(line.chars().count() - 1))), line));
+            },
+            '.' => {
+                let start = (row, col);
+                let mut cur = Token::Dot(Position::new(start, start));
+                if let Some('.') = chars.peek() {
+                    chars.next();
+                    col = col.saturating_add(1);
+                    cur = match chars.peek() {
+                        Some('.') => next_and!(chars, col, Token::Ellipsis(Position::new(start, (row, col.saturating_add(1))))),
+                        Some('=') => next_and!(chars, col, Token::DotDotEq(Position::new(start, (row, col.saturating_add(1))))),
+                        _ => Token::DotDot(Position::new(start, (row, col))),
+                    };
+                }
+                output.push(cur);
+            },
+            '`' if options.backtick_identifiers => {
+                let start = (row, col);
+                let mut val = String::new();
+                loop {
+                    match chars.next() {
+                        Some('`') => break,
+                        Some('\n') | None => return Err(LexError::new(here.clone(), LexErrorKind::UnterminatedBacktickIdentifier)),
+                        Some(n) => val.push(n),
+                    }
+                    col = col.saturating_add(1);
+                }
+                col = col.saturating_add(1);
+
+                if val.is_empty() { return Err(LexError::new(here.clone(), LexErrorKind::EmptyBacktickIdentifier)); }
+                output.push(Token::Identifier(Position::new(start, (row, col)), val));
+            },
+            '@' if options.attributes => {
+                let start = (row, col);
+                let mut name = String::new();
+                while let Some(&n) = chars.peek() {
+                    if !n.is_ascii_alphanumeric() && n != '_' { break; }
+                    col = col.saturating_add(1);
+                    name.push(n);
+                    chars.next();
+                }
+
+                if !name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+                    return Err(LexError::new(here.clone(), LexErrorKind::InvalidAttributeName));
+                }
+
+                // the arguments are cut out up to the matching `)` (ignoring parens inside
+                // string literals) and lexed on their own, starting from their real position
+                let mut args = vec![];
+                if chars.peek() == Some(&'(') {
+                    chars.next();
+                    col = col.saturating_add(1);
+                    let (args_start, args_byte) = ((row, col.saturating_add(1)), chars.offset());
+                    let (mut depth, mut in_string, mut inner, mut prev) = (1, false, String::new(), '(');
+                    loop {
+                        let Some(n) = chars.next() else {
+                            return Err(LexError::new(here.clone(), LexErrorKind::UnbalancedAttribute));
+                        };
+
+                        (row, col) = columns.after((row, col), prev);
+                        prev = n;
+
+                        match n {
+                            '"' => in_string = !in_string,
+                            '(' if !in_string => depth += 1,
+                            ')' if !in_string => {
+                                depth -= 1;
+                                if depth == 0 { break; }
+                            },
+                            _ => {},
+                        }
+                        inner.push(n);
+                    }
+
+                    // recoverable errors in the arguments go to the same sink, so tokenize_all carries on past them
+                    let recorded = errors.as_ref().map_or(0, |errors| errors.len());
+                    let work_budget = options.work_budget.map(|budget| budget.saturating_sub(chars.steps.get()));
+                    let nested = lex(&inner, &LexerOptions { start: Some(args_start), work_budget, ..options.clone() }, &mut args, errors.as_deref_mut(), false)
+                        .map_err(|err| LexError { position: err.position.shift_bytes(args_byte), ..err })?;
+                    chars.steps.set(chars.steps.get().saturating_add(nested.steps));
+                    if let Some(errors) = errors.as_deref_mut() {
+                        for err in &mut errors[recorded..] {
+                            err.position = err.position.shift_bytes(args_byte);
+                        }
+                    }
+                    shift_token_bytes(&mut args, args_byte);
+                }
+
+                output.push(Token::Attribute(Position::new(start, (row, col)), Box::new((name, args))));
+            },
+            ',' => output.push(Token::Comma(Position::new((row, col), (row, col)))),
+            ':' => {
+                let mut cur = Token::Colon(Position::new((row, col), (row, col)));
+                if let Some(':') = chars.peek() {
+                    cur = next_and!(chars, col, Token::ColonColon(Position::new((row, col), (row, col.saturating_add(1)))));
+                }
+                output.push(cur);
+            },
+            '(' => output.push(Token::LParen(Position::new((row, col), (row, col)))),
+            ')' => output.push(Token::RParen(Position::new((row, col), (row, col)))),
+            '{' => output.push(Token::LBrace(Position::new((row, col), (row, col)))),
+            '}' => output.push(Token::RBrace(Position::new((row, col), (row, col)))),
+            '<' => {
+                let mut cur = Token::Lt(Position::new((row, col), (row, col)));
+                if let Some(next) = chars.peek() {
+                    cur = match next {
+                        '=' => next_and!(chars, col, Token::LtEq(Position::new((row, col), (row, col.saturating_add(1))))),
+                        '<' => {
+                            let start = (row, col);
+                            chars.next();
+                            col = col.saturating_add(1);
+                            if let Some('=') = chars.peek() {
+                                next_and!(chars, col, Token::LShiftEq(Position::new(start, (row, col.saturating_add(1)))))
+                            } else if options.pipeline_arrows && let Some('-') = chars.peek() {
+                                next_and!(chars, col, Token::LShiftSub(Position::new(start, (row, col.saturating_add(1)))))
+                            } else {
+                                Token::LShift(Position::new(start, (row, col)))
+                            }
+                        }
+                        _ => cur,
+                    };
+                }
+                output.push(cur);
+            },
+            '>' => {
+                let mut cur = Token::Gt(Position::new((row, col), (row, col)));
+                if let Some(next) = chars.peek() {
+                    cur = match next {
+                        '=' => next_and!(chars, col, Token::GtEq(Position::new((row, col), (row, col.saturating_add(1))))),
+                        '>' => {
+                            let start = (row, col);
+                            chars.next();
+                            col = col.saturating_add(1);
+                            if let Some('=') = chars.peek() {
+                                next_and!(chars, col, Token::RShiftEq(Position::new(start, (row, col.saturating_add(1)))))
+                            } else {
+                                Token::RShift(Position::new(start, (row, col)))
+                            }
+                        }
+                        _ => cur,
+                    };
+                }
+                output.push(cur);
+            },
+            '+' => {
+                let mut cur = Token::Add(Position::new((row, col), (row, col)));
+                if let Some(next) = chars.peek() {
+                    cur = match next {
+                        '+' => next_and!(chars, col, Token::AddAdd(Position::new((row, col), (row, col.saturating_add(1))))),
+                        '=' => next_and!(chars, col, Token::AddEq(Position::new((row, col), (row, col.saturating_add(1))))),
+                        _ => cur,
+                    };
+                }
+                output.push(cur);
+            },
+            '-' => {
+                let mut cur = Token::Sub(Position::new((row, col), (row, col)));
+                if let Some(next) = chars.peek() {
+                    cur = match next {
+                        '-' if options.dash_dash_comment => {
+                            loop {
+                                match chars.peek() {
+                                    Some('\n') => break,
+                                    None => break,
+                                    _ => next_and!(chars, col, continue),
+                                }
+                            }
+
+                            col = col.saturating_add(1);
+                            continue
+                        },
+                        '-' => next_and!(chars, col, Token::SubSub(Position::new((row, col), (row, col.saturating_add(1))))),
+                        '=' => next_and!(chars, col, Token::SubEq(Position::new((row, col), (row, col.saturating_add(1))))),
+                        '>' => {
+                            let start = (row, col);
+                            chars.next();
+                            col = col.saturating_add(1);
+                            if options.pipeline_arrows && let Some('>') = chars.peek() {
+                                next_and!(chars, col, Token::ArrowGt(Position::new(start, (row, col.saturating_add(1)))))
+                            } else {
+                                Token::Arrow(Position::new(start, (row, col)))
+                            }
+                        },
+                        _ => cur,
+                    };
+                }
+                output.push(cur);
+            },
+            '*' => {
+                let mut cur = Token::Mul(Position::new((row, col), (row, col)));
+                if let Some(next) = chars.peek() {
+                    cur = match next {
+                        '=' => next_and!(chars, col, Token::MulEq(Position::new((row, col), (row, col.saturating_add(1))))),
+                        _ => cur,
+                    }
+                }
+                output.push(cur);
+            },
+            '/' => {
+                let mut cur = Token::Div(Position::new((row, col), (row, col)));
+                if let Some(next) = chars.peek() {
+                    cur = match next {
+                        '/' => {
+                            loop {
+                                match chars.peek() {
+                                    Some('\n') => break,
+                                    None => break,
+                                    _ => next_and!(chars, col, continue),
+                                }
+                            }
+
+                            col = col.saturating_add(1);
+                            continue
+                        },
+                        '*' => {
+                            // The opening `*` can't double as the closing one, so `/*/` is unterminated
+                            chars.next();
+                            col = col.saturating_add(1);
+                            let (mut depth, mut prev) = (1, '*');
+                            loop {
+                                let Some(c) = chars.next() else {
+                                    return Err(LexError::new(here.clone(), LexErrorKind::UnterminatedBlockComment));
+                                };
+
+                                (row, col) = columns.after((row, col), prev);
+                                prev = c;
+                                match c {
+                                    '*' if chars.peek() == Some(&'/') => {
+                                        chars.next();
+                                        (col, prev) = (col.saturating_add(1), '/');
+                                        depth -= 1;
+                                        if depth == 0 { break }
+                                    },
+                                    '/' if options.nested_block_comments && chars.peek() == Some(&'*') => {
+                                        chars.next();
+                                        (col, prev) = (col.saturating_add(1), '*');
+                                        depth += 1;
+                                    },
+                                    _ => {},
+                                }
+                            }
+
+                            col = col.saturating_add(1);
+                            continue
+                        },
+                        '=' => next_and!(chars, col, Token::DivEq(Position::new((row, col), (row, col.saturating_add(1))))),
+                        _ => cur,
+                    };
+                }
+                output.push(cur);
+            },
+            '=' => {
+                let mut cur = Token::Eq(Position::new((row, col), (row, col)));
+                if let Some(next) = chars.peek() {
+                    cur = match next {
+                        '=' => next_and!(chars, col, Token::EqEq(Position::new((row, col), (row, col.saturating_add(1))))),
+                        '>' => next_and!(chars, col, Token::FatArrow(Position::new((row, col), (row, col.saturating_add(1))))),
+                        _ => cur,
+                    };
+                } 
+                output.push(cur);
+            },
+            '!' => {
+                let mut cur = Token::Not(Position::new((row, col), (row, col)));
+                if let Some(next) = chars.peek() {
+                    cur = match next {
+                        '=' => next_and!(chars, col, Token::NotEq(Position::new((row, col), (row, col.saturating_add(1))))),
+                        _ => cur,
+                    }
+                }
+                output.push(cur);
+            },
+            '|' => {
+                let mut cur = Token::Or(Position::new((row, col), (row, col)));
+                if let Some(next) = chars.peek() {
+                    cur = match next {
+                        '=' => next_and!(chars, col, Token::OrEq(Position::new((row, col), (row, col.saturating_add(1))))),
+                        '|' => next_and!(chars, col, Token::OrOr(Position::new((row, col), (row, col.saturating_add(1))))),
+                        '>' if options.pipe_forward => next_and!(chars, col, Token::PipeForward(Position::new((row, col), (row, col.saturating_add(1))))),
+                        _ => cur,
+                    }
+                }
+                output.push(cur);
+            },
+            '&' => {
+                let mut cur = Token::And(Position::new((row, col), (row, col)));
+                if let Some(next) = chars.peek() {
+                    cur = match next {
+                        '=' => next_and!(chars, col, Token::AndEq(Position::new((row, col), (row, col.saturating_add(1))))),
+                        '&' => next_and!(chars, col, Token::AndAnd(Position::new((row, col), (row, col.saturating_add(1))))),
+                        _ => cur,
+                    }
+                } 
+                output.push(cur);
+            },
+            '^' => {
+                let mut cur = Token::Xor(Position::new((row, col), (row, col)));
+                if let Some(next) = chars.peek() {
+                    cur = match next {
+                        '=' => next_and!(chars, col, Token::XorEq(Position::new((row, col), (row, col.saturating_add(1))))),
+                        _ => cur,
+                    }
+                }
+                output.push(cur);
+            },
+            '%' => {
+                let mut cur = Token::Mod(Position::new((row, col), (row, col)));
+                if let Some(next) = chars.peek() {
+                    cur = match next {
+                        '=' => next_and!(chars, col, Token::ModEq(Position::new((row, col), (row, col.saturating_add(1))))),
+                        _ => cur,
+                    };
+                }
+                output.push(cur);
+            },
+            '?' => {
+                let mut cur = Token::Question(Position::new((row, col), (row, col)));
+                if let Some(next) = chars.peek() {
+                    cur = match next {
+                        '.' => next_and!(chars, col, Token::QuestionDot(Position::new((row, col), (row, col.saturating_add(1))))),
+                        '?' => {
+                            let start = (row, col);
+                            chars.next();
+                            col = col.saturating_add(1);
+                            if let Some('=') = chars.peek() {
+                                next_and!(chars, col, Token::QuestionQuestionEq(Position::new(start, (row, col.saturating_add(1)))))
+                            } else {
+                                Token::QuestionQuestion(Position::new(start, (row, col)))
+                            }
+                        },
+                        _ => cur,
+                    };
+                }
+                output.push(cur);
+            },
+            _ => {
+                let err = LexError::new(here.clone(), LexErrorKind::UnknownChar(char));
+                let Some(errors) = errors.as_deref_mut() else { return Err(err) };
+                errors.push(err);
+                output.push(Token::Error(Position::new((row, col), (row, col)), char.to_string()));
+            },
+        };
+
+        if let Some(token) = output.get(lexed) && options.disallowed_ops.contains(token.kind()) {
+            let position = token.position().clone().with_bytes(start_byte, chars.offset());
+            return Err(LexError::new(position, LexErrorKind::DisallowedOperator(token.kind())));
+        }
+
+        // only worth counting when this char added an error; warnings don't count
+        let is_error = |err: &LexError| err.kind.severity() == Severity::Error;
+        if let Some(errors) = errors.as_deref()
+            && errors[seen..].iter().any(is_error)
+            && options.max_errors.is_some_and(|max| errors[reported..].iter().filter(|err| is_error(err)).count() >= max) {
+            return Err(LexError::new(here.clone(), LexErrorKind::TooManyErrors));
+        }
+
+        // a newline has already moved on to the next line
+        if char != '\n' {
+            col = col.saturating_add(1);
+        }
+    }
+
+    if chars.exhausted() {
+        let here = Position::new((row, col), (row, col)).with_bytes(start_byte, start_byte);
+        return Err(LexError::new(here, LexErrorKind::BudgetExhausted));
+    }
+    chars.end_line(chars.line_chars);
+    Ok((row, col))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::mem::discriminant;
+    use super::*;
+
+    macro_rules! variant_eq {
+        ($left:ident, $right:ident) => { discriminant(&$left) == discriminant(&$right) };
+        ($left:ident, $right:expr) =>  { discriminant(&$left) == discriminant(&$right) };
+        ($left:expr, $right:ident) =>  { discriminant(&$left) == discriminant(&$right) };
+        ($left:expr, $right:expr) =>   { discriminant(&$left) == discriminant(&$right) };
+    }
+
+
+    #[test]
+    fn operators() {
+        let input = "-> => == <= >= += -= *= /= %= >>= <<= >> << != |= &= ^= ++ -- || &&".to_string();
+        let tokens = tokenize(&input).unwrap();
+        let mut token = tokens.iter();
+        let pos = Position::new((0, 0), (0, 0));
+        assert!(variant_eq!(*token.next().unwrap(), Token::Arrow(pos.clone())));
         assert!(variant_eq!(*token.next().unwrap(), Token::FatArrow(pos.clone())));
         assert!(variant_eq!(*token.next().unwrap(), Token::EqEq(pos.clone())));
         assert!(variant_eq!(*token.next().unwrap(), Token::LtEq(pos.clone())));
@@ -343,13 +1899,1343 @@ mod tests {
         assert!(variant_eq!(*token.next().unwrap(), Token::Eof(pos.clone())));
     }
 
+    #[test]
+    fn single_eof() {
+        let tokens = tokenize("a + 1").unwrap();
+        assert_eq!(tokens.iter().filter(|t| t.is_eof()).count(), 1);
+        assert!(tokens.last().unwrap().is_eof());
+        assert!(!tokens[0].is_eof());
+
+        let tokens = tokenize("").unwrap();
+        assert_eq!(tokens.len(), 1);
+        assert!(tokens[0].is_eof());
+    }
+
+    #[test]
+    fn literal_prefixes() {
+        let options = LexerOptions { literal_prefixes: HashSet::from(["u".to_string()]), ..Default::default() };
+        let tokens = tokenize_with_options("u\"http://x\" d\"2024\" u \"y\"", &options).unwrap();
+        assert_eq!(tokens[0], Token::TaggedStrLiteral(Position::new((1, 1), (1, 11)), Box::new(("u".to_string(), "http://x".to_string()))));
+        assert!(variant_eq!(tokens[1], Token::Identifier(Position::new((0, 0), (0, 0)), String::new())));
+        assert!(variant_eq!(tokens[2], Token::StrLiteral(Position::new((0, 0), (0, 0)), String::new())));
+        assert!(variant_eq!(tokens[3], Token::Identifier(Position::new((0, 0), (0, 0)), String::new())));
+        assert!(variant_eq!(tokens[4], Token::StrLiteral(Position::new((0, 0), (0, 0)), String::new())));
+
+        let tokens = tokenize("u\"x\"").unwrap();
+        assert!(variant_eq!(tokens[0], Token::Identifier(Position::new((0, 0), (0, 0)), String::new())));
+    }
+
+    #[test]
+    fn cursor_lookup() {
+        let tokens = tokenize("foo (bar)\n  baz").unwrap();
+        assert_eq!(token_at(&tokens, 1, 1), Some(&tokens[0]));
+        assert_eq!(token_at(&tokens, 1, 2), Some(&tokens[0]));
+        assert_eq!(token_at(&tokens, 1, 3), Some(&tokens[0]));
+        assert_eq!(token_at(&tokens, 1, 4), None);
+        assert_eq!(token_at(&tokens, 1, 5), Some(&tokens[1]));
+        assert_eq!(token_at(&tokens, 1, 6), Some(&tokens[2]));
+        assert_eq!(token_at(&tokens, 1, 8), Some(&tokens[2]));
+        assert_eq!(token_at(&tokens, 1, 9), Some(&tokens[3]));
+        assert_eq!(token_at(&tokens, 2, 1), None);
+        assert_eq!(token_at(&tokens, 2, 4), Some(&tokens[4]));
+        assert_eq!(token_at(&tokens, 0, 1), None);
+        assert_eq!(token_at(&tokens, 3, 1), None);
+    }
+
+    #[test]
+    fn eq_disambiguation() {
+        let tokens = tokenize("===").unwrap();
+        assert_eq!(tokens[0], Token::EqEq(Position::new((1, 1), (1, 2))));
+        assert_eq!(tokens[1], Token::Eq(Position::new((1, 3), (1, 3))));
+        assert_eq!(tokens[2], Token::Eof(Position::new((1, 4), (1, 4))));
+
+        let tokens = tokenize("=>=").unwrap();
+        assert_eq!(tokens[0], Token::FatArrow(Position::new((1, 1), (1, 2))));
+        assert_eq!(tokens[1], Token::Eq(Position::new((1, 3), (1, 3))));
+
+        let tokens = tokenize("== => =").unwrap();
+        assert_eq!(tokens[0], Token::EqEq(Position::new((1, 1), (1, 2))));
+        assert_eq!(tokens[1], Token::FatArrow(Position::new((1, 4), (1, 5))));
+        assert_eq!(tokens[2], Token::Eq(Position::new((1, 7), (1, 7))));
+        assert_eq!(tokens[3], Token::Eof(Position::new((1, 8), (1, 8))));
+    }
+
+    #[test]
+    fn custom_keywords() {
+        let keywords: &[&str] = &["fn", "let"];
+        let options = LexerOptions { keywords: keywords.iter().map(|k| k.to_string()).collect(), ..Default::default() };
+        let tokens = tokenize_with_options("let fn_name fn", &options).unwrap();
+        assert_eq!(tokens[0], Token::Keyword(Position::new((1, 1), (1, 3)), "let".to_string()));
+        assert_eq!(tokens[1], Token::Identifier(Position::new((1, 5), (1, 11)), "fn_name".to_string()));
+        assert_eq!(tokens[2], Token::Keyword(Position::new((1, 13), (1, 14)), "fn".to_string()));
+
+        let tokens = tokenize("let").unwrap();
+        assert!(variant_eq!(tokens[0], Token::Identifier(Position::new((0, 0), (0, 0)), String::new())));
+    }
+
+    #[test]
+    fn token_kind_eq() {
+        let tokens = tokenize("a -> 1").unwrap();
+        assert_eq!(tokens[0].kind(), TokenKind::Identifier);
+        assert!(tokens[1] == TokenKind::Arrow);
+        assert!(tokens[1] != TokenKind::FatArrow);
+        assert!(tokens[2] == TokenKind::NumLiteral);
+        assert!(tokens[3] == TokenKind::Eof);
+    }
+
+    #[test]
+    fn confusable_operators() {
+        let warnings = lint_confusables(&tokenize("a =< b =!c").unwrap());
+        assert_eq!(warnings, vec![
+            LexWarning::ConfusableOperator {
+                found: "=<".to_string(),
+                suggestion: "<=".to_string(),
+                position: Position::new((1, 3), (1, 4)),
+            },
+            LexWarning::ConfusableOperator {
+                found: "=!".to_string(),
+                suggestion: "!=".to_string(),
+                position: Position::new((1, 8), (1, 9)),
+            },
+        ]);
+
+        assert!(lint_confusables(&tokenize("a = !b <= c != d").unwrap()).is_empty());
+
+        let tokens = tokenize_with_source("a =< b", 2).unwrap();
+        let [LexWarning::ConfusableOperator { position, .. }] = &lint_confusables(&tokens)[..] else { panic!() };
+        assert_eq!(position.source_id(), 2);
+        assert_eq!(position.byte_range(), 2..4);
+        assert_eq!(&position.merge(tokens[1].position()), position);
+    }
+
+    #[test]
+    fn byte_offset_positions() {
+        let source = "x = \"h\u{e9}llo\" + yy";
+        let index = LineIndex::new(source);
+        let tokens = tokenize(source).unwrap();
+
+        let start = source.find('"').unwrap();
+        assert_eq!(&Position::from_byte_offsets(start, start + 8, &index), tokens[2].position());
+        let start = source.find("yy").unwrap();
+        assert_eq!(&Position::from_byte_offsets(start, start + 2, &index), tokens[4].position());
+
+        let index = LineIndex::new("ab\ncd\n");
+        assert_eq!(Position::from_byte_offsets(3, 5, &index), Position::new((2, 1), (2, 2)));
+        assert_eq!(Position::from_byte_offsets(6, 6, &index), Position::new((3, 1), (3, 1)));
+
+        let source = "\tfoo + \tbar";
+        let options = LexerOptions { tab_width: 4, ..Default::default() };
+        let tokens = tokenize_with_options(source, &options).unwrap();
+        let index = LineIndex::new(source).with_tab_width(4);
+        assert_eq!(&Position::from_byte_offsets(1, 4, &index), tokens[0].position());
+        assert_eq!(&Position::from_byte_offsets(8, 11, &index), tokens[2].position());
+        assert_eq!(index.offset((1, 13)), 8);
+        assert_eq!(index.offset((1, 3)), 0);
+
+        // tabs inside comments, strings and attribute arguments move to the next stop too
+        let source = "/*\t*/x \"\t\" y @a(\"\t\") z";
+        let options = LexerOptions { tab_width: 4, attributes: true, ..Default::default() };
+        let tokens = tokenize_with_options(source, &options).unwrap();
+        let index = LineIndex::new(source).with_tab_width(4);
+        for token in &tokens[..tokens.len() - 1] {
+            let pos = token.position();
+            assert_eq!(&Position::from_byte_offsets(pos.start_byte, pos.end_byte, &index), pos);
+        }
+        assert_eq!(tokens[2].position().start, (1, 15));
+    }
+
+    #[test]
+    fn raw_identifiers() {
+        let options = LexerOptions {
+            keywords: HashSet::from(["type".to_string()]),
+            raw_identifiers: true,
+            ..Default::default()
+        };
+        let tokens = tokenize_with_options("r#type type r", &options).unwrap();
+        assert_eq!(tokens[0], Token::RawIdentifier(Position::new((1, 1), (1, 6)), "type".to_string()));
+        assert_eq!(tokens[0].identifier_name(), Some("type"));
+        assert_eq!(tokens[0].identifier_raw_spelling(), Some("r#type".to_string()));
+        assert!(tokens[1] == TokenKind::Keyword);
+        assert_eq!(tokens[1].identifier_name(), None);
+        assert_eq!(tokens[2].identifier_name(), Some("r"));
+        assert_eq!(tokens[2].identifier_raw_spelling(), Some("r".to_string()));
+    }
+
+    #[test]
+    fn raw_identifiers_disabled() {
+        assert_eq!(tokenize("r#type"), Err(LexError::new(Position::new((1, 2), (1, 2)), LexErrorKind::UnknownChar('#'))));
+    }
+
+    #[test]
+    fn token_kind_set() {
+        let mut set = TokenKindSet::from([TokenKind::Add, TokenKind::Sub, TokenKind::Eof]);
+        assert!(set.contains(TokenKind::Add));
+        assert!(set.contains(TokenKind::Eof));
+        assert!(!set.contains(TokenKind::Mul));
+        assert!(!set.contains(TokenKind::Identifier));
+
+        set.insert(TokenKind::Mul);
+        assert!(set.contains(TokenKind::Mul));
+
+        let tokens = tokenize("a * b").unwrap();
+        assert!(set.contains(tokens[1].kind()));
+        assert!(!TokenKindSet::new().contains(tokens[1].kind()));
+        assert_eq!(tokens.iter().map(Token::kind).collect::<TokenKindSet>(),
+                   TokenKindSet::from([TokenKind::Identifier, TokenKind::Mul, TokenKind::Eof]));
+    }
+
+    #[test]
+    fn long_identifier() {
+        let tokens = tokenize(&("a".repeat(1 << 20) + " b")).unwrap();
+        assert_eq!(tokens.len(), 3);
+        assert_eq!(tokens[0].identifier_name().map(str::len), Some(1 << 20));
+        assert_eq!(tokens[1], Token::Identifier(Position::new((1, (1 << 20) + 2), (1, (1 << 20) + 2)), "b".to_string()));
+
+        let options = LexerOptions { max_identifier_len: Some(8), ..Default::default() };
+        assert_eq!(tokenize_with_options("abcdefgh", &options).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn identifier_too_long() {
+        let options = LexerOptions { max_identifier_len: Some(8), ..Default::default() };
+        assert_eq!(tokenize_with_options("a abcdefghi", &options), Err(LexError::new(Position::new((1, 3), (1, 3)), LexErrorKind::IdentifierTooLong)));
+    }
+
+    #[test]
+    fn formatter_spacing() {
+        let spacing = |input: &str| {
+            let tokens = tokenize(input).unwrap();
+            tokens.windows(2).map(|pair| pair[1].requires_space_before(&pair[0])).collect::<Vec<_>>()
+        };
+        assert_eq!(spacing("a + b"), vec![true, true, false]);
+        assert_eq!(spacing("f(x)"), vec![false, false, false, false]);
+        assert_eq!(spacing("a.b"), vec![false, false, false]);
+        assert_eq!(spacing("f(a, b)"), vec![false, false, false, true, false, false]);
+        assert_eq!(spacing("f(!x) { i++ }"), vec![false, false, false, false, true, true, false, true, false]);
+    }
+
+    #[test]
+    fn statement_terminator() {
+        let tokens = tokenize("a; b").unwrap();
+        assert_eq!(tokens[1], Token::Semicolon(Position::new((1, 2), (1, 2))));
+
+        let options = LexerOptions { statement_terminator: Some('\n'), ..Default::default() };
+        let tokens = tokenize_with_options("a\nb", &options).unwrap();
+        assert_eq!(tokens[1], Token::Semicolon(Position::new((1, 2), (1, 2))));
+        assert!(tokens[2] == TokenKind::Identifier);
+        assert_eq!(tokens[2].position().start.0, 2);
+    }
+
+    #[test]
+    fn statement_terminator_disabled() {
+        let options = LexerOptions { statement_terminator: None, ..Default::default() };
+        assert_eq!(tokenize_with_options("a;", &options), Err(LexError::new(Position::new((1, 2), (1, 2)), LexErrorKind::UnknownChar(';'))));
+    }
+
+    #[test]
+    fn default_options() {
+        let options = LexerOptions::default();
+        assert!(options.literal_prefixes.is_empty());
+        assert!(options.keywords.is_empty());
+        assert!(!options.raw_identifiers);
+        assert_eq!(options.max_identifier_len, None);
+        assert_eq!(options.statement_terminator, Some(';'));
+        assert!(!options.dash_dash_comment);
+        assert_eq!(options.start, None);
+        assert!(!options.attributes);
+        assert_eq!(options.max_line_length, None);
+        assert_eq!(options.disallowed_ops, TokenKindSet::new());
+        assert!(!options.backtick_identifiers);
+        assert!(!options.unicode_whitespace);
+        assert!(!options.detect_mixed_line_endings);
+        assert_eq!(options.work_budget, None);
+        assert!(!options.strict_number_suffix);
+        assert!(!options.preprocessor_directives);
+        assert_eq!(options.max_errors, Some(100));
+        assert!(!options.unicode_digits);
+        assert!(!options.nested_block_comments);
+        assert_eq!(options.tab_width, 1);
+        assert_eq!(options.source_id, 0);
+        assert!(!options.pipe_forward);
+        assert!(!options.zero_based);
+        assert!(!options.pipeline_arrows);
+
+        let input = "fn f(a) -> { r = a << 2; u\"x\" /* c */ }";
+        assert_eq!(tokenize(input).unwrap(), tokenize_with_options(input, &options).unwrap());
+    }
+
+    #[test]
+    fn following_kinds() {
+        let tokens = tokenize("f(x) + 1").unwrap();
+        let annotated = with_following_kinds(tokens.clone());
+        assert_eq!(annotated.len(), tokens.len());
+        for (i, (token, following)) in annotated.iter().enumerate() {
+            assert_eq!(token, &tokens[i]);
+            assert_eq!(*following, tokens.get(i + 1).map(Token::kind));
+        }
+        assert_eq!(annotated[0].1, Some(TokenKind::LParen));
+        assert_eq!(annotated.last().unwrap().1, None);
+    }
+
+    #[test]
+    fn token_to_char() {
+        let tokens = tokenize("( + x += ) ^").unwrap();
+        assert_eq!(char::try_from(&tokens[0]), Ok('('));
+        assert_eq!(char::try_from(&tokens[1]), Ok('+'));
+        assert_eq!(char::try_from(&tokens[2]), Err(TokenKind::Identifier));
+        assert_eq!(char::try_from(&tokens[3]), Err(TokenKind::AddEq));
+        assert_eq!(char::try_from(&tokens[4]), Ok(')'));
+        assert_eq!(char::try_from(&tokens[5]), Ok('^'));
+        assert_eq!(char::try_from(&tokens[6]), Err(TokenKind::Eof));
+    }
+
+    #[test]
+    fn dots() {
+        let kinds = |input: &str| tokenize(input).unwrap().iter().map(Token::kind).collect::<Vec<_>>();
+        assert_eq!(kinds("a.b"), vec![TokenKind::Identifier, TokenKind::Dot, TokenKind::Identifier, TokenKind::Eof]);
+        assert_eq!(kinds("a..b"), vec![TokenKind::Identifier, TokenKind::DotDot, TokenKind::Identifier, TokenKind::Eof]);
+        assert_eq!(kinds("a...b"), vec![TokenKind::Identifier, TokenKind::Ellipsis, TokenKind::Identifier, TokenKind::Eof]);
+        assert_eq!(kinds("a..=b"), vec![TokenKind::Identifier, TokenKind::DotDotEq, TokenKind::Identifier, TokenKind::Eof]);
+        assert_eq!(kinds("a....b"), vec![TokenKind::Identifier, TokenKind::Ellipsis, TokenKind::Dot, TokenKind::Identifier, TokenKind::Eof]);
+        assert_eq!(kinds("1..2"), vec![TokenKind::NumLiteral, TokenKind::DotDot, TokenKind::NumLiteral, TokenKind::Eof]);
+
+        let tokens = tokenize(". .. ..= ... x").unwrap();
+        assert_eq!(tokens[0], Token::Dot(Position::new((1, 1), (1, 1))));
+        assert_eq!(tokens[1], Token::DotDot(Position::new((1, 3), (1, 4))));
+        assert_eq!(tokens[2], Token::DotDotEq(Position::new((1, 6), (1, 8))));
+        assert_eq!(tokens[3], Token::Ellipsis(Position::new((1, 10), (1, 12))));
+        assert_eq!(tokens[4].position().start, (1, 14));
+    }
+
+    #[test]
+    fn dash_dash_comment() {
+        let tokens = tokenize("-- note").unwrap();
+        assert!(tokens[0] == TokenKind::SubSub);
+        assert!(tokens[1] == TokenKind::Identifier);
+        assert!(tokens[2].is_eof());
+
+        let options = LexerOptions { dash_dash_comment: true, ..Default::default() };
+        let tokens = tokenize_with_options("-- note", &options).unwrap();
+        assert_eq!(tokens.len(), 1);
+
+        let tokens = tokenize_with_options("a - b -- note\nc", &options).unwrap();
+        let kinds: Vec<_> = tokens.iter().map(Token::kind).collect();
+        assert_eq!(kinds, vec![TokenKind::Identifier, TokenKind::Sub, TokenKind::Identifier, TokenKind::Identifier, TokenKind::Eof]);
+        assert_eq!(tokens[3].position().start.0, 2);
+    }
+
+    #[test]
+    fn shift_position() {
+        let pos = Position::new((2, 3), (2, 5));
+        assert_eq!(pos.shift(10, 4), Position::new((12, 7), (12, 9)));
+        assert_eq!(pos.shift(-1, -2), Position::new((1, 1), (1, 3)));
+        assert_eq!(pos.shift(-5, -4), Position::new((0, 0), (0, 1)));
+        assert_eq!(pos.shift(0, 0), pos);
+    }
+
+    #[test]
+    fn arrow_and_comparison_boundaries() {
+        let tokens = tokenize("a>=b").unwrap();
+        assert_eq!(tokens[1], Token::GtEq(Position::new((1, 2), (1, 3))));
+        assert_eq!(tokens[2], Token::Identifier(Position::new((1, 4), (1, 4)), "b".to_string()));
+
+        let tokens = tokenize("a->b").unwrap();
+        assert_eq!(tokens[1], Token::Arrow(Position::new((1, 2), (1, 3))));
+        assert_eq!(tokens[2], Token::Identifier(Position::new((1, 4), (1, 4)), "b".to_string()));
+
+        let tokens = tokenize("a=>b").unwrap();
+        assert_eq!(tokens[1], Token::FatArrow(Position::new((1, 2), (1, 3))));
+        assert_eq!(tokens[2], Token::Identifier(Position::new((1, 4), (1, 4)), "b".to_string()));
+
+        let tokens = tokenize("a>>b>>=c- >d").unwrap();
+        assert_eq!(tokens[1], Token::RShift(Position::new((1, 2), (1, 3))));
+        assert_eq!(tokens[3], Token::RShiftEq(Position::new((1, 5), (1, 7))));
+        assert_eq!(tokens[4], Token::Identifier(Position::new((1, 8), (1, 8)), "c".to_string()));
+        assert_eq!(tokens[5], Token::Sub(Position::new((1, 9), (1, 9))));
+        assert_eq!(tokens[6], Token::Gt(Position::new((1, 11), (1, 11))));
+    }
+
+    #[test]
+    fn start_position() {
+        let options = LexerOptions { start: Some((42, 5)), ..Default::default() };
+        let tokens = tokenize_with_options("ab + c\nd", &options).unwrap();
+        assert_eq!(tokens[0], Token::Identifier(Position::new((42, 5), (42, 6)), "ab".to_string()));
+        assert_eq!(tokens[1], Token::Add(Position::new((42, 8), (42, 8))));
+        assert_eq!(tokens[3].position().start.0, 43);
+        assert_eq!(tokens[4].position().start.0, 43);
+    }
+
+    #[test]
+    fn token_size() {
+        // no variant may carry more than one String inline
+        assert!(std::mem::size_of::<Token>() <= std::mem::size_of::<(Position, String, usize)>());
+    }
+
+    #[test]
+    fn attributes() {
+        let options = LexerOptions { attributes: true, ..Default::default() };
+        let tokens = tokenize_with_options("@foo x", &options).unwrap();
+        assert_eq!(tokens[0], Token::Attribute(Position::new((1, 1), (1, 4)), Box::new(("foo".to_string(), vec![]))));
+        assert_eq!(tokens[1], Token::Identifier(Position::new((1, 6), (1, 6)), "x".to_string()));
+
+        let tokens = tokenize_with_options("@bar(1, (2)) y", &options).unwrap();
+        assert_eq!(tokens[0], Token::Attribute(Position::new((1, 1), (1, 12)), Box::new(("bar".to_string(), vec![
+            Token::NumLiteral(Position::new((1, 6), (1, 6)), "1".to_string()),
+            Token::Comma(Position::new((1, 7), (1, 7))),
+            Token::LParen(Position::new((1, 9), (1, 9))),
+            Token::NumLiteral(Position::new((1, 10), (1, 10)), "2".to_string()),
+            Token::RParen(Position::new((1, 11), (1, 11))),
+        ]))));
+        assert_eq!(tokens[1], Token::Identifier(Position::new((1, 14), (1, 14)), "y".to_string()));
+
+        let tokens = tokenize_with_options("@doc(\")\")", &options).unwrap();
+        assert_eq!(tokens.len(), 2);
+    }
+
+    #[test]
+    fn attributes_unbalanced() {
+        let options = LexerOptions { attributes: true, ..Default::default() };
+        assert_eq!(tokenize_with_options("@bar(1, (2)", &options), Err(LexError::new(Position::new((1, 1), (1, 1)), LexErrorKind::UnbalancedAttribute)));
+    }
+
+    #[test]
+    fn attributes_recover() {
+        let options = LexerOptions { attributes: true, ..Default::default() };
+        let source = "@a(1 $) x";
+        let (tokens, errors) = tokenize_all_with_options(source, &options);
+        let Token::Attribute(_, attr) = &tokens[0] else { panic!("{:?}", tokens[0]) };
+        assert_eq!(attr.1.iter().map(Token::kind).collect::<Vec<_>>(), [TokenKind::NumLiteral, TokenKind::Error]);
+        assert_eq!(tokens[1], Token::Identifier(Position::new((1, 9), (1, 9)), "x".to_string()));
+        assert_eq!(errors, [LexError::new(Position::new((1, 6), (1, 6)), LexErrorKind::UnknownChar('$'))]);
+        assert_eq!(&source[errors[0].position.byte_range()], "$");
+        assert_eq!(&source[attr.1[1].position().byte_range()], "$");
+    }
+
+    #[test]
+    fn attributes_disabled() {
+        assert_eq!(tokenize("@foo"), Err(LexError::new(Position::new((1, 1), (1, 1)), LexErrorKind::UnknownChar('@'))));
+    }
+
+    #[test]
+    fn line_too_long() {
+        let options = LexerOptions { max_line_length: Some(10), ..Default::default() };
+        let (tokens, warnings) = tokenize_with_warnings("short = 1\nmuch_longer = 2\n", &options).unwrap();
+        assert_eq!(tokens.len(), 7);
+        assert_eq!(warnings, vec![LexWarning::LineTooLong { row: 2, length: 15 }]);
+
+        let (_, warnings) = tokenize_with_warnings("much_longer = 2", &LexerOptions::default()).unwrap();
+        assert!(warnings.is_empty());
+
+        // lines inside strings and comments count too; `\r\n` isn't part of the length
+        let input = "x = \"a\r\nmuch longer string\"\r\n/* a\ncomment that runs on */ y\nabcdefghijk";
+        let (_, warnings) = tokenize_with_warnings(input, &options).unwrap();
+        let rows = [(2, 19), (4, 25), (5, 11)].map(|(row, length)| LexWarning::LineTooLong { row, length });
+        assert_eq!(warnings, rows);
+    }
+
+    #[test]
+    fn literal_predicates() {
+        let options = LexerOptions { literal_prefixes: HashSet::from(["u".to_string()]), ..Default::default() };
+        let tokens = tokenize_with_options("12 \"s\" u\"t\" x", &options).unwrap();
+        assert!(tokens[0].is_int_literal() && !tokens[0].is_string_literal());
+        assert!(tokens[1].is_string_literal() && !tokens[1].is_int_literal());
+        assert!(tokens[2].is_string_literal() && !tokens[2].is_int_literal());
+        assert!(!tokens[3].is_string_literal() && !tokens[3].is_int_literal());
+    }
+
+    #[test]
+    fn disallowed_ops() {
+        let options = LexerOptions { disallowed_ops: TokenKindSet::from([TokenKind::LShift]), ..Default::default() };
+        assert_eq!(tokenize_with_options("a + b <= c", &options).unwrap().len(), 6);
+    }
+
+    #[test]
+    fn disallowed_ops_error() {
+        let options = LexerOptions { disallowed_ops: TokenKindSet::from([TokenKind::LShift]), ..Default::default() };
+        let err = tokenize_with_options("a << b", &options).unwrap_err();
+        assert_eq!(err.kind, LexErrorKind::DisallowedOperator(TokenKind::LShift));
+        assert_eq!(err.position.start, (1, 3));
+    }
+
+    #[test]
+    fn token_slices() {
+        let source = "x = foo(\"h\u{e9}\", b) + 1";
+        let tokens = tokenize(source).unwrap();
+        assert_eq!(slice_tokens(source, &tokens[2..5]), "foo(\"h\u{e9}\"");
+        assert_eq!(slice_tokens(source, &tokens[4..9]), "\"h\u{e9}\", b) +");
+        assert_eq!(slice_tokens(source, &tokens[..1]), "x");
+        assert_eq!(slice_tokens(source, &tokens[9..]), "1");
+        assert_eq!(slice_tokens(source, &[]), "");
+
+        let options = LexerOptions { start: Some((3, 1)), ..Default::default() };
+        let tokens = tokenize_with_options("foo bar", &options).unwrap();
+        assert_eq!(slice_tokens("foo bar", &tokens[1..2]), "bar");
+
+        let options = LexerOptions { tab_width: 4, ..Default::default() };
+        let tokens = tokenize_with_options("\tfoo + bar", &options).unwrap();
+        assert_eq!(slice_tokens("\tfoo + bar", &tokens[0..1]), "foo");
+        assert_eq!(slice_tokens("\tfoo + bar", &tokens[1..3]), "+ bar");
+    }
+
+    #[test]
+    fn incdec_roles() {
+        let roles = |input: &str| {
+            let tokens = tokenize(input).unwrap();
+            (0..tokens.len() - 1)
+                .map(|i| disambiguate_incdec(i.checked_sub(1).map(|p| &tokens[p]), &tokens[i]))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(roles("a++"), vec![IncDecRole::Error, IncDecRole::Postfix]);
+        assert_eq!(roles("++a"), vec![IncDecRole::Prefix, IncDecRole::Error]);
+        assert_eq!(roles("a++ ++b"), vec![IncDecRole::Error, IncDecRole::Postfix, IncDecRole::Prefix, IncDecRole::Error]);
+        assert_eq!(roles("(a)-- - --b"), vec![
+            IncDecRole::Error, IncDecRole::Error, IncDecRole::Error, IncDecRole::Postfix,
+            IncDecRole::Error, IncDecRole::Prefix, IncDecRole::Error,
+        ]);
+    }
+
+    #[test]
+    fn backtick_identifiers() {
+        let options = LexerOptions { backtick_identifiers: true, ..Default::default() };
+        let tokens = tokenize_with_options("`my col` + `x<y`", &options).unwrap();
+        assert_eq!(tokens[0], Token::Identifier(Position::new((1, 1), (1, 8)), "my col".to_string()));
+        assert_eq!(tokens[1], Token::Add(Position::new((1, 10), (1, 10))));
+        assert_eq!(tokens[2], Token::Identifier(Position::new((1, 12), (1, 16)), "x<y".to_string()));
+    }
+
+    #[test]
+    fn backtick_identifiers_unterminated() {
+        let options = LexerOptions { backtick_identifiers: true, ..Default::default() };
+        assert_eq!(tokenize_with_options("a `b\nc`", &options), Err(LexError::new(Position::new((1, 3), (1, 3)), LexErrorKind::UnterminatedBacktickIdentifier)));
+    }
+
+    #[test]
+    fn eof_after_last_token() {
+        let tokens = tokenize(">").unwrap();
+        assert_eq!(tokens, vec![Token::Gt(Position::new((1, 1), (1, 1))), Token::Eof(Position::new((1, 2), (1, 2)))]);
+
+        let tokens = tokenize("abc").unwrap();
+        assert_eq!(tokens[0], Token::Identifier(Position::new((1, 1), (1, 3)), "abc".to_string()));
+        assert_eq!(tokens[1], Token::Eof(Position::new((1, 4), (1, 4))));
+
+        let tokens = tokenize("123").unwrap();
+        assert_eq!(tokens[0], Token::NumLiteral(Position::new((1, 1), (1, 3)), "123".to_string()));
+        assert_eq!(tokens[1], Token::Eof(Position::new((1, 4), (1, 4))));
+
+        let tokens = tokenize("a //x").unwrap();
+        assert_eq!(tokens.len(), 2);
+        assert_eq!(tokens[1], Token::Eof(Position::new((1, 6), (1, 6))));
+    }
+
+    #[test]
+    fn eof_in_string() {
+        assert_eq!(tokenize("abc \"de"), Err(LexError::new(Position::new((1, 5), (1, 5)), LexErrorKind::UnterminatedString)));
+        let options = LexerOptions { literal_prefixes: HashSet::from(["u".to_string()]), ..Default::default() };
+        assert_eq!(tokenize_with_options("u\"x", &options), Err(LexError::new(Position::new((1, 2), (1, 2)), LexErrorKind::UnterminatedString)));
+    }
+
+    #[test]
+    fn tokens_with_index() {
+        let source = "let s = \"\u{e9}t\u{e9}\" + x\ny";
+        let (tokens, index) = tokenize_with_index(source).unwrap();
+        for token in &tokens[..6] {
+            let pos = token.position();
+            assert_eq!(index.line_col(index.offset(pos.start)), pos.start);
+            assert_eq!(index.line_col(index.offset(pos.end)), pos.end);
+        }
+        assert_eq!(index.offset(tokens[4].position().start), source.find('+').unwrap());
+        assert_eq!(index.line_col(source.find('y').unwrap()).0, tokens[6].position().start.0);
+
+        // the lexer's lines, including those inside strings and comments, match a plain scan
+        for source in ["", "a\n", "\"a\nb\" /* c\r\nd */\n\ne", "x\n\n"] {
+            let (_, index) = tokenize_with_index(source).unwrap();
+            assert_eq!(index.line_starts, LineIndex::new(source).line_starts);
+        }
+    }
+
+    #[test]
+    fn unicode_whitespace() {
+        let options = LexerOptions { unicode_whitespace: true, ..Default::default() };
+        let tokens = tokenize_with_options("a\u{a0}b\u{3000}+", &options).unwrap();
+        assert_eq!(tokens[0], Token::Identifier(Position::new((1, 1), (1, 1)), "a".to_string()));
+        assert_eq!(tokens[1], Token::Identifier(Position::new((1, 3), (1, 3)), "b".to_string()));
+        assert_eq!(tokens[2], Token::Add(Position::new((1, 5), (1, 5))));
+    }
+
+    #[test]
+    fn unicode_whitespace_disabled() {
+        assert_eq!(tokenize("a\u{a0}b"), Err(LexError::new(Position::new((1, 2), (1, 2)), LexErrorKind::UnknownChar('\u{a0}'))));
+    }
+
+    #[test]
+    fn delimiter_predicates() {
+        let tokens = tokenize("( ) { } < > a").unwrap();
+        let open: Vec<_> = tokens.iter().map(Token::is_open_delimiter).collect();
+        let close: Vec<_> = tokens.iter().map(Token::is_close_delimiter).collect();
+        assert_eq!(open, vec![true, false, true, false, false, false, false, false]);
+        assert_eq!(close, vec![false, true, false, true, false, false, false, false]);
+    }
+
+    #[test]
+    fn mixed_line_endings() {
+        let options = LexerOptions { detect_mixed_line_endings: true, ..Default::default() };
+        let (tokens, warnings) = tokenize_with_warnings("a\r\nb\nc\r\nd\n", &options).unwrap();
+        assert_eq!(tokens.len(), 5);
+        assert_eq!(warnings, vec![LexWarning::MixedLineEndings]);
+
+        let (_, warnings) = tokenize_with_warnings("a\r\nb\r\n", &options).unwrap();
+        assert!(warnings.is_empty());
+        let (_, warnings) = tokenize_with_warnings("a\nb\n", &options).unwrap();
+        assert!(warnings.is_empty());
+    }
+
+    #[test]
+    fn work_budget() {
+        assert_eq!(tokenize_with_budget("a + bc", 64).unwrap().len(), 4);
+    }
+
+    #[test]
+    fn work_budget_exhausted() {
+        assert!(matches!(tokenize_with_budget(&"a + b ".repeat(100), 64), Err(LexError { kind: LexErrorKind::BudgetExhausted, .. })));
+
+        // a single long token, or the last one, can't run past the budget either
+        let long = "a".repeat(10_000);
+        let options = LexerOptions { work_budget: Some(10), attributes: true, ..Default::default() };
+        for input in [format!("\"{long}\""), format!("/*{long}*/"), format!("@a(\"{long}\")"), long.clone(), "a + bcdefghij".to_string()] {
+            assert!(matches!(tokenize_with_options(&input, &options), Err(LexError { kind: LexErrorKind::BudgetExhausted, .. })), "{input:.20}");
+            let (_, errors) = tokenize_all_with_options(&input, &options);
+            assert!(matches!(&errors[..], [LexError { kind: LexErrorKind::BudgetExhausted, .. }]), "{input:.20}");
+        }
+    }
+
+    #[test]
+    fn block_comment_bounds() {
+        let unterminated = Err(LexError::new(Position::new((1, 1), (1, 1)), LexErrorKind::UnterminatedBlockComment));
+        assert_eq!(tokenize("/*/"), unterminated);
+        assert_eq!(tokenize("/*/ a"), unterminated);
+        assert_eq!(tokenize("/**/").unwrap().len(), 1);
+        let tokens = tokenize("/* */ a").unwrap();
+        assert_eq!(tokens.len(), 2);
+        assert_eq!(tokens[0].kind(), TokenKind::Identifier);
+    }
+
+    #[test]
+    fn category_tags() {
+        for (tag, kind) in TokenKind::ALL.iter().enumerate() {
+            assert_eq!(kind.tag() as usize, tag);
+            assert_eq!(TokenKind::from_tag(kind.tag()), Some(*kind));
+        }
+        assert_eq!(TokenKind::ALL.iter().map(|kind| kind.tag()).collect::<HashSet<_>>().len(), TokenKind::ALL.len());
+        assert_eq!(TokenKind::from_tag(TokenKind::ALL.len() as u16), None);
+
+        let tokens = tokenize("a (1) == \"s\"").unwrap();
+        let tags = tokens.iter().map(Token::category_tag).collect::<Vec<_>>();
+        assert_eq!(tags, [0, 7, 3, 8, 20, 4, 51]);
+    }
+
+    #[test]
+    fn tokenize_all_recovers() {
+        let (tokens, errors) = tokenize_all("a $ b \"c");
+        let kinds = tokens.iter().map(Token::kind).collect::<Vec<_>>();
+        assert_eq!(kinds, [TokenKind::Identifier, TokenKind::Error, TokenKind::Identifier, TokenKind::Error, TokenKind::Identifier, TokenKind::Eof]);
+        assert_eq!(errors, [
+            LexError::new(Position::new((1, 3), (1, 3)), LexErrorKind::UnknownChar('$')),
+            LexError::new(Position::new((1, 7), (1, 7)), LexErrorKind::UnterminatedString),
+        ]);
+        assert_eq!(tokens[3], Token::Error(Position::new((1, 7), (1, 7)), "\"".to_string()));
+
+        let (tokens, errors) = tokenize_all("a + b");
+        assert_eq!(tokens, tokenize("a + b").unwrap());
+        assert!(errors.is_empty());
+    }
+
+    #[test]
+    fn error_tokens() {
+        let (tokens, errors) = tokenize_all("@`~");
+        assert_eq!(errors.len(), 3);
+        assert_eq!(&tokens[..3], [
+            Token::Error(Position::new((1, 1), (1, 1)), "@".to_string()),
+            Token::Error(Position::new((1, 2), (1, 2)), "`".to_string()),
+            Token::Error(Position::new((1, 3), (1, 3)), "~".to_string()),
+        ]);
+        assert!(tokens[3].is_eof());
+    }
+
+    #[test]
+    fn null_coalescing() {
+        let tokens = tokenize("a ?? b").unwrap();
+        assert_eq!(tokens[1], Token::QuestionQuestion(Position::new((1, 3), (1, 4))));
+        assert_eq!(tokens[2].kind(), TokenKind::Identifier);
+
+        let tokens = tokenize("a ??= b").unwrap();
+        assert_eq!(tokens[1], Token::QuestionQuestionEq(Position::new((1, 3), (1, 5))));
+        assert_eq!(tokens[2], Token::Identifier(Position::new((1, 7), (1, 7)), "b".to_string()));
+
+        let tokens = tokenize("a ? b").unwrap();
+        assert_eq!(tokens[1], Token::Question(Position::new((1, 3), (1, 3))));
+        assert_eq!(tokens.len(), 4);
+    }
+
+    #[test]
+    fn optional_chaining() {
+        let kinds = |input: &str| tokenize(input).unwrap().iter().map(Token::kind).collect::<Vec<_>>();
+        assert_eq!(kinds("a?.b"), [TokenKind::Identifier, TokenKind::QuestionDot, TokenKind::Identifier, TokenKind::Eof]);
+        assert_eq!(kinds("a ? .b"), [TokenKind::Identifier, TokenKind::Question, TokenKind::Dot, TokenKind::Identifier, TokenKind::Eof]);
+        assert_eq!(kinds("a?"), [TokenKind::Identifier, TokenKind::Question, TokenKind::Eof]);
+
+        let tokens = tokenize("a?.b").unwrap();
+        assert_eq!(tokens[1], Token::QuestionDot(Position::new((1, 2), (1, 3))));
+        assert_eq!(tokens[2], Token::Identifier(Position::new((1, 4), (1, 4)), "b".to_string()));
+    }
+
+    #[test]
+    fn unterminated_string_position() {
+        let err = tokenize("\"abc\ndef").unwrap_err();
+        assert_eq!(err, LexError::new(Position::new((1, 1), (1, 1)), LexErrorKind::UnterminatedString));
+        let err = tokenize("x\n  \"abc\ndef").unwrap_err();
+        assert_eq!(err.position.start.0, 2);
+    }
+
+    #[test]
+    fn block_comment_rows() {
+        let err = tokenize("a /* line one\nline two").unwrap_err();
+        assert_eq!(err, LexError::new(Position::new((1, 3), (1, 3)), LexErrorKind::UnterminatedBlockComment));
+
+        let tokens = tokenize("a /* x\n\n y */ b /**/ c").unwrap();
+        assert_eq!(tokens[1], Token::Identifier(Position::new((3, 7), (3, 7)), "b".to_string()));
+        assert_eq!(tokens[2], Token::Identifier(Position::new((3, 14), (3, 14)), "c".to_string()));
+    }
+
+    #[test]
+    fn source_map() {
+        let tokens = tokenize("a + b").unwrap();
+        let mut map = SourceMap::new();
+        map.add((1, 1), &tokens[0]);
+        map.add((1, 2), &tokens[1]);
+        map.add((1, 3), &tokens[2]);
+        map.add((3, 1), &tokens[0]);
+        let mappings = map.mappings();
+        assert_eq!(mappings, "AAAA,CAAE,CAAE;;AAAJ");
+        assert_eq!(mappings.split([',', ';']).filter(|segment| !segment.is_empty()).count(), 4);
+
+        let mut map = SourceMap::new();
+        map.add((1, 17), &tokens[0]);
+        assert_eq!(map.mappings(), "gBAAA");
+        assert_eq!(SourceMap::new().mappings(), "");
+    }
+
+    #[test]
+    fn zero_based_helpers() {
+        let options = LexerOptions { zero_based: true, ..Default::default() };
+        let tokens = tokenize_with_options("a + b", &options).unwrap();
+        let mut map = SourceMap::zero_based();
+        map.add((0, 0), &tokens[0]);
+        map.add((0, 1), &tokens[1]);
+        map.add((0, 2), &tokens[2]);
+        map.add((2, 0), &tokens[0]);
+        assert_eq!(map.mappings(), "AAAA,CAAE,CAAE;;AAAJ");
+
+        let tokens = tokenize_with_options("foo bar", &options).unwrap();
+        assert_eq!(slice_tokens("foo bar", &tokens[0..2]), "foo bar");
+        assert_eq!(slice_tokens("foo bar", &tokens[1..2]), "bar");
+
+        let err = tokenize_with_options("a\nb $", &options).unwrap_err();
+        assert_eq!(err.position.start, (1, 2));
+        assert_eq!(err.render("a\nb $"), "error[E0001]: unknown char `$` at 1:2\n  |\n1 | b $\n  |   ^\n");
+    }
+
+    #[test]
+    fn render_error() {
+        let source = "a = 1;\n\tb @ 2";
+        let err = tokenize(source).unwrap_err();
+        assert_eq!(err.render(source), "error[E0001]: unknown char `@` at 2:4\n  |\n2 | \tb @ 2\n  | \t  ^\n");
+
+        let source = "abc \"";
+        let err = tokenize(source).unwrap_err();
+        assert_eq!(err.render(source), "error[E0002]: unterminated string literal at 1:5\n  |\n1 | abc \"\n  |     ^\n");
+
+        // past the last line, e.g. at EOF after a trailing newline
+        let err = LexError::new(Position::from_byte_offsets(2, 2, &LineIndex::new("a\n")), LexErrorKind::BudgetExhausted);
+        assert_eq!(err.render("a\n"), "error[E0011]: work budget exhausted at 2:1\n  |\n2 | \n  | ^\n");
+
+        // the caret sits under the char whatever tab_width made of its column
+        let options = LexerOptions { tab_width: 4, ..Default::default() };
+        let err = tokenize_with_options("\tx $", &options).unwrap_err();
+        assert_eq!(err.position.start, (1, 7));
+        assert_eq!(err.render("\tx $"), "error[E0001]: unknown char `$` at 1:7\n  |\n1 | \tx $\n  | \t  ^\n");
+    }
+
+    #[test]
+    fn error_display() {
+        let err = tokenize("x = \"abc").unwrap_err();
+        assert_eq!(err.to_string(), "[E0002] unterminated string literal at 1:5");
+        let options = LexerOptions { disallowed_ops: TokenKindSet::from([TokenKind::LShift]), ..Default::default() };
+        let err = tokenize_with_options("a << b", &options).unwrap_err();
+        assert_eq!(err.to_string(), "[E0010] disallowed operator LShift at 1:3");
+
+        let boxed: Box<dyn std::error::Error> = tokenize("a # b").unwrap_err().into();
+        assert_eq!(boxed.to_string(), "[E0001] unknown char `#` at 1:3");
+        assert!(boxed.source().is_none());
+    }
+
+    #[test]
+    fn strict_number_suffix() {
+        let kinds = tokenize("1abc").unwrap().iter().map(Token::kind).collect::<Vec<_>>();
+        assert_eq!(kinds, [TokenKind::NumLiteral, TokenKind::Identifier, TokenKind::Eof]);
+
+        let options = LexerOptions { strict_number_suffix: true, ..Default::default() };
+        let err = tokenize_with_options("x = 1abc", &options).unwrap_err();
+        assert_eq!(err, LexError::new(Position::new((1, 5), (1, 5)), LexErrorKind::InvalidNumberSuffix));
+        assert_eq!(tokenize_with_options("1 + abc2", &options).unwrap().len(), 4);
+    }
+
+    #[test]
+    fn error_codes() {
+        let kinds = [
+            (LexErrorKind::UnknownChar('#'), "E0001"),
+            (LexErrorKind::UnterminatedString, "E0002"),
+            (LexErrorKind::UnterminatedBlockComment, "E0003"),
+            (LexErrorKind::IdentifierTooLong, "E0004"),
+            (LexErrorKind::InvalidRawIdentifier, "E0005"),
+            (LexErrorKind::UnterminatedBacktickIdentifier, "E0006"),
+            (LexErrorKind::EmptyBacktickIdentifier, "E0007"),
+            (LexErrorKind::InvalidAttributeName, "E0008"),
+            (LexErrorKind::UnbalancedAttribute, "E0009"),
+            (LexErrorKind::DisallowedOperator(TokenKind::Add), "E0010"),
+            (LexErrorKind::BudgetExhausted, "E0011"),
+            (LexErrorKind::InvalidNumberSuffix, "E0012"),
+            (LexErrorKind::MixedIndentation, "E0013"),
+            (LexErrorKind::TooManyErrors, "E0014"),
+        ];
+        for (kind, code) in kinds {
+            assert_eq!(kind.code(), code);
+        }
+    }
+
+    #[test]
+    fn token_kind_names() {
+        for kind in TokenKind::ALL {
+            assert_eq!(kind.to_string().parse::<TokenKind>(), Ok(kind));
+        }
+        assert_eq!(TokenKind::Arrow.to_string(), "Arrow");
+        assert_eq!("Identifier".parse(), Ok(TokenKind::Identifier));
+        assert!("arrow".parse::<TokenKind>().is_err());
+    }
+
+    #[test]
+    fn unknown_char_suggestion() {
+        assert_eq!(tokenize("x = \u{201c}hi\u{201d}").unwrap_err().suggestion(), Some("\"".to_string()));
+        assert_eq!(tokenize("f\u{ff08}x)").unwrap_err().suggestion(), Some("(".to_string()));
+        assert_eq!(tokenize("a \u{2013} b").unwrap_err().suggestion(), Some("-".to_string()));
+        assert_eq!(tokenize("a # b").unwrap_err().suggestion(), None);
+        assert_eq!(tokenize("\"a").unwrap_err().suggestion(), None);
+    }
+
+    #[test]
+    fn kind_histogram() {
+        let counts = histogram(&tokenize("a + b + 1 == c").unwrap());
+        assert_eq!(counts[&TokenKind::Identifier], 3);
+        assert_eq!(counts[&TokenKind::Add], 2);
+        assert_eq!(counts[&TokenKind::NumLiteral], 1);
+        assert_eq!(counts[&TokenKind::Eof], 1);
+        assert_eq!(counts.get(&TokenKind::Sub), None);
+        assert_eq!(counts.values().sum::<usize>(), 8);
+    }
+
+    #[cfg(feature = "diagnostics")]
+    #[test]
+    fn miette_diagnostic() {
+        use miette::Diagnostic;
+        let err = tokenize("a \u{ff1b}").unwrap_err();
+        assert_eq!(err.code().unwrap().to_string(), "E0001");
+        assert_eq!(err.help().unwrap().to_string(), "did you mean `;`?");
+        assert!(tokenize("a #").unwrap_err().help().is_none());
+        let label = err.labels().unwrap().next().unwrap();
+        assert_eq!((label.offset(), label.len()), (2, 3));
+    }
+
+    #[test]
+    fn preprocessor_directives() {
+        let options = LexerOptions { preprocessor_directives: true, ..Default::default() };
+        let tokens = tokenize_with_options("#define X 1", &options).unwrap();
+        assert_eq!(tokens[0], Token::PreprocessorDirective(Position::new((1, 1), (1, 11)), "#define X 1".to_string()));
+        assert!(tokens[1].is_eof());
+
+        let tokens = tokenize_with_options("a\r\n  #if DEBUG\r\nb", &options).unwrap();
+        assert_eq!(tokens[1], Token::PreprocessorDirective(Position::new((2, 3), (2, 11)), "#if DEBUG".to_string()));
+        assert_eq!(tokens[2].kind(), TokenKind::Identifier);
+
+        // mid-line or without a directive word, `#` is still unknown
+        assert!(tokenize_with_options("a #if", &options).is_err());
+        assert!(tokenize_with_options("\"a\nb\" #define X", &options).is_err());
+        assert!(tokenize_with_options("# 1", &options).is_err());
+        assert!(tokenize("#define X 1").is_err());
+
+        // a comment earlier on the line counts as something before the `#`, as do an attribute's parens
+        assert!(tokenize_with_options("/* c */ #define X 1", &options).is_err());
+        assert_eq!(tokenize_with_options("/* c */\n#define X 1", &options).unwrap()[0].kind(), TokenKind::PreprocessorDirective);
+        let options = LexerOptions { attributes: true, ..options };
+        assert_eq!(tokenize_with_options("@a(#define X)", &options).unwrap_err().kind, LexErrorKind::UnknownChar('#'));
+    }
+
+    #[test]
+    fn warning_severity() {
+        let (tokens, errors) = tokenize_all("a\n \tb\n\t c $\n\td");
+        assert_eq!(tokens.iter().filter(|token| token.kind() == TokenKind::Identifier).count(), 4);
+        let found = errors.iter().map(|err| (err.position.start.0, err.kind.severity())).collect::<Vec<_>>();
+        assert_eq!(found, [(2, Severity::Warning), (3, Severity::Warning), (3, Severity::Error)]);
+        assert_eq!(errors[2].kind, LexErrorKind::UnknownChar('$'));
+
+        let (tokens, errors) = tokenize_all(" \ta + b");
+        assert!(errors.iter().all(|err| err.kind.severity() == Severity::Warning));
+        assert_eq!(tokens.len(), 4);
+    }
+
+    #[test]
+    fn mixed_indentation() {
+        let (_, errors) = tokenize_all("a\n \t b");
+        assert_eq!(errors, [LexError::new(Position::new((2, 1), (2, 1)), LexErrorKind::MixedIndentation)]);
+        assert_eq!(errors[0].position.byte_range(), 2..3);
+
+        // only whitespace a line starts with is indentation
+        for source in ["/*\n \tx\n*/ a", "s = \"\n \tx\n\"", "a \t b", "a /*\n*/ \tb"] {
+            assert_eq!(tokenize_all(source).1, [], "{source:?}");
+        }
+        let options = LexerOptions { attributes: true, ..Default::default() };
+        assert_eq!(tokenize_all_with_options("@a( \tb)", &options).1, []);
+        assert_eq!(tokenize_all_with_options("@a(\n \tb)", &options).1[0].position.start, (2, 1));
+
+        // a snippet starts at its own column
+        let options = LexerOptions { start: Some((3, 5)), ..Default::default() };
+        assert_eq!(tokenize_all_with_options(" \tb", &options).1[0].position.start, (3, 5));
+    }
+
+    #[test]
+    fn closing_delimiter_runs() {
+        for (input, kind) in [("f(g(h()))", TokenKind::RParen), ("{{{}}}", TokenKind::RBrace)] {
+            let tokens = tokenize(input).unwrap();
+            let cols = tokens.iter().filter(|token| token.kind() == kind).map(|token| token.position().clone()).collect::<Vec<_>>();
+            let len = input.len();
+            assert_eq!(cols, (len - 2..=len).map(|col| Position::new((1, col), (1, col))).collect::<Vec<_>>());
+        }
+
+        let tokens = tokenize(")))").unwrap();
+        assert_eq!(tokens.len(), 4);
+        assert_eq!(tokens[3], Token::Eof(Position::new((1, 4), (1, 4))));
+    }
+
+    #[test]
+    fn max_errors() {
+        let input = "$".repeat(10_000);
+        let (tokens, errors) = tokenize_all(&input);
+        assert_eq!(errors.len(), 101);
+        assert_eq!(errors[100], LexError::new(Position::new((1, 100), (1, 100)), LexErrorKind::TooManyErrors));
+        assert_eq!(tokens.len(), 101);
+        assert!(tokens[100].is_eof());
+
+        let options = LexerOptions { max_errors: Some(3), ..Default::default() };
+        assert_eq!(tokenize_all_with_options(&input, &options).1.len(), 4);
+        let options = LexerOptions { max_errors: None, ..Default::default() };
+        assert_eq!(tokenize_all_with_options(&input, &options).1.len(), 10_000);
+
+        // the limit is on errors seen, so clean input and warnings never reach it
+        let options = LexerOptions { max_errors: Some(0), ..Default::default() };
+        let (tokens, errors) = tokenize_all_with_options("a b\n \tc", &options);
+        assert_eq!(tokens.len(), 4);
+        assert_eq!(errors.iter().map(|err| err.kind.clone()).collect::<Vec<_>>(), [LexErrorKind::MixedIndentation]);
+        let (tokens, errors) = tokenize_all_with_options("a $ b", &options);
+        assert_eq!(tokens.len(), 3);
+        assert_eq!(errors[1], LexError::new(Position::new((1, 3), (1, 3)), LexErrorKind::TooManyErrors));
+    }
+
+    #[test]
+    fn duplicate_adjacent() {
+        let kinds = TokenKindSet::from([TokenKind::Semicolon, TokenKind::Comma]);
+        let tokens = tokenize("f(a,, b);; g(c); ;").unwrap();
+        let found = find_duplicate_adjacent(&tokens, &kinds);
+        assert_eq!(found, [
+            Position::new((1, 4), (1, 5)),
+            Position::new((1, 9), (1, 10)),
+            Position::new((1, 16), (1, 18)),
+        ]);
+
+        let touching = found.iter().filter(|pos| pos.start.0 == pos.end.0 && pos.end.1 == pos.start.1 + 1).count();
+        assert_eq!(touching, 2);
+        assert!(find_duplicate_adjacent(&tokens, &TokenKindSet::from([TokenKind::Identifier])).is_empty());
+
+        let tokens = tokenize_with_source("a;;", 2).unwrap();
+        let found = find_duplicate_adjacent(&tokens, &kinds);
+        assert_eq!(found[0].source_id(), 2);
+        assert_eq!(found[0].byte_range(), 1..3);
+        assert_eq!(found[0].merge(tokens[1].position()), found[0]);
+    }
+
+    #[test]
+    fn byte_offsets() {
+        let source = "a \"üñ\" bc";
+        let tokens = tokenize(source).unwrap();
+        let slices = tokens.iter().map(|token| &source[token.position().byte_range()]).collect::<Vec<_>>();
+        assert_eq!(slices, ["a", "\"üñ\"", "bc", ""]);
+        assert_eq!(tokens[3].position().byte_range(), 11..11);
+
+        let options = LexerOptions { attributes: true, ..Default::default() };
+        let source = "@doc(\"ü\", 1) x";
+        let tokens = tokenize_with_options(source, &options).unwrap();
+        let Token::Attribute(pos, attribute) = &tokens[0] else { panic!("expected an attribute") };
+        assert_eq!(&source[pos.byte_range()], "@doc(\"ü\", 1)");
+        assert_eq!(&source[attribute.1[0].position().byte_range()], "\"ü\"");
+        assert_eq!(&source[attribute.1[2].position().byte_range()], "1");
+        assert_eq!(&source[tokens[1].position().byte_range()], "x");
+
+        assert_eq!(tokenize("a ü").unwrap_err().position.byte_range(), 2..4);
+        assert_eq!(tokenize("\"ü\" \"a").unwrap_err().position.byte_range(), 5..6);
+        let options = LexerOptions { literal_prefixes: HashSet::from(["u".to_string()]), ..Default::default() };
+        assert_eq!(tokenize_with_options("\"é\" u\"x", &options).unwrap_err().position.byte_range(), 6..7);
+        assert_eq!(tokenize_all("\"é\" u\"x").1[0].position.byte_range(), 6..7);
+    }
+
+    #[test]
+    fn position_display() {
+        assert_eq!(Position::new((3, 5), (3, 7)).to_string(), "3:5..3:7");
+        assert_eq!(Position::new((3, 5), (3, 5)).to_string(), "3:5");
+        let tokens = tokenize("a >>= b").unwrap();
+        assert_eq!(tokens[1].position().to_string(), "1:3..1:5");
+    }
+
+    #[test]
+    fn position_accessors() {
+        let mut tokens = tokenize("x \"s\" 12 >= )").unwrap();
+        let starts = tokens.iter().map(|token| token.position().start).collect::<Vec<_>>();
+        assert_eq!(starts, [(1, 1), (1, 3), (1, 7), (1, 10), (1, 13), (1, 14)]);
+
+        for token in &mut tokens {
+            *token.position_mut() = token.position().shift(1, 0);
+        }
+        assert_eq!(tokens[1], Token::StrLiteral(Position::new((2, 3), (2, 5)), "s".to_string()));
+        assert_eq!(tokens[3].position(), &Position::new((2, 10), (2, 11)));
+        assert_eq!(tokens[5].position().start, (2, 14));
+    }
+
+    #[test]
+    fn unicode_digits() {
+        let options = LexerOptions { unicode_digits: true, ..Default::default() };
+        let source = "\u{663}\u{664} + 1\u{6f5}";
+        let tokens = tokenize_with_options(source, &options).unwrap();
+        assert_eq!(tokens[0], Token::NumLiteral(Position::new((1, 1), (1, 2)), "34".to_string()));
+        assert_eq!(tokens[2], Token::NumLiteral(Position::new((1, 6), (1, 7)), "15".to_string()));
+        assert_eq!(&source[tokens[2].position().byte_range()], "1\u{6f5}");
+
+        assert_eq!(tokenize("\u{663}").unwrap_err().kind, LexErrorKind::UnknownChar('\u{663}'));
+        assert!(tokenize_with_options("\u{bd}", &options).is_err());
+    }
+
+    #[test]
+    fn type_ascription() {
+        for input in ["x: Int", "x:Int", "x : Int"] {
+            let tokens = tokenize(input).unwrap();
+            let kinds = tokens.iter().map(Token::kind).collect::<Vec<_>>();
+            assert_eq!(kinds, [TokenKind::Identifier, TokenKind::Colon, TokenKind::Identifier, TokenKind::Eof]);
+            assert!(is_type_ascription(Some(&tokens[0]), &tokens[1]));
+        }
+
+        let tokens = tokenize("x :: Int").unwrap();
+        assert_eq!(tokens[1], Token::ColonColon(Position::new((1, 3), (1, 4))));
+        assert!(!is_type_ascription(Some(&tokens[0]), &tokens[1]));
+
+        let tokens = tokenize("(1): Int").unwrap();
+        assert!(!is_type_ascription(Some(&tokens[2]), &tokens[3]));
+        assert!(!is_type_ascription(None, &tokens[3]));
+    }
+
+    #[test]
+    fn operator_end_columns() {
+        let tokens = tokenize("==").unwrap();
+        assert_eq!(tokens[0], Token::EqEq(Position::new((1, 1), (1, 2))));
+
+        let source = "a <= b << c <<= d += e ++ f *= g /= h != i |= j || k &= l && m ^= n %= o";
+        let tokens = tokenize(source).unwrap();
+        for token in tokens.iter().filter(|token| !matches!(token, Token::Identifier(..) | Token::Eof(_))) {
+            let pos = token.position();
+            assert_eq!(pos.start.0, pos.end.0, "{token:?}");
+            assert_eq!(&source[pos.byte_range()].chars().count(), &(pos.end.1 - pos.start.1 + 1), "{token:?}");
+        }
+        assert_eq!(tokens[5], Token::LShiftEq(Position::new((1, 13), (1, 15))));
+        assert_eq!(tokens.last().unwrap().position().start, (1, source.len() + 1));
+    }
+
+    #[test]
+    fn nested_block_comments() {
+        let source = "/* a /* b */ c */ d";
+        let kinds = tokenize(source).unwrap().iter().map(Token::kind).collect::<Vec<_>>();
+        assert_eq!(kinds, [TokenKind::Identifier, TokenKind::Mul, TokenKind::Div, TokenKind::Identifier, TokenKind::Eof]);
+
+        let options = LexerOptions { nested_block_comments: true, ..Default::default() };
+        let tokens = tokenize_with_options(source, &options).unwrap();
+        assert_eq!(tokens[0], Token::Identifier(Position::new((1, 19), (1, 19)), "d".to_string()));
+        assert_eq!(tokens.len(), 2);
+
+        let err = tokenize_with_options("/* a /* b */ c", &options).unwrap_err();
+        assert_eq!(err.kind, LexErrorKind::UnterminatedBlockComment);
+        assert_eq!(err.position.start, (1, 1));
+    }
+
+    #[test]
+    fn column_accounting() {
+        let tokens = tokenize("foo bar").unwrap();
+        assert_eq!(tokens[0], Token::Identifier(Position::new((1, 1), (1, 3)), "foo".to_string()));
+        assert_eq!(tokens[1], Token::Identifier(Position::new((1, 5), (1, 7)), "bar".to_string()));
+
+        // every line starts back at column 1, whether a space or a newline came before
+        let tokens = tokenize("12 x\n12 x\r\nfoo bar").unwrap();
+        assert_eq!(tokens[2], Token::NumLiteral(Position::new((2, 1), (2, 2)), "12".to_string()));
+        assert_eq!(tokens[3], Token::Identifier(Position::new((2, 4), (2, 4)), "x".to_string()));
+        assert_eq!(tokens[5], Token::Identifier(Position::new((3, 5), (3, 7)), "bar".to_string()));
+
+        let options = LexerOptions { statement_terminator: Some('\n'), ..Default::default() };
+        let tokens = tokenize_with_options("a\nb", &options).unwrap();
+        assert_eq!(tokens[2], Token::Identifier(Position::new((2, 1), (2, 1)), "b".to_string()));
+    }
+
+    #[test]
+    fn multiline_strings() {
+        let tokens = tokenize("\"line1\nline2\" x").unwrap();
+        assert_eq!(tokens[0], Token::StrLiteral(Position::new((1, 1), (2, 6)), "line1\nline2".to_string()));
+        assert_eq!(tokens[1], Token::Identifier(Position::new((2, 8), (2, 8)), "x".to_string()));
+
+        let options = LexerOptions { literal_prefixes: HashSet::from(["b".to_string()]), ..Default::default() };
+        let tokens = tokenize_with_options("b\"\n\"\ny", &options).unwrap();
+        assert_eq!(tokens[0].position(), &Position::new((1, 1), (2, 1)));
+        assert_eq!(tokens[1], Token::Identifier(Position::new((3, 1), (3, 1)), "y".to_string()));
+
+        // an unterminated string is reported where it opened, and recovery resumes on that line
+        let (tokens, errors) = tokenize_all("a \"b\nc");
+        assert_eq!(errors[0].position.start, (1, 3));
+        assert_eq!(tokens[3], Token::Identifier(Position::new((2, 1), (2, 1)), "c".to_string()));
+    }
+
+    #[test]
+    fn trailing_backslash() {
+        // there are no line continuations, so a backslash is an unknown char wherever it appears
+        assert_eq!(tokenize("a \\"), Err(LexError::new(Position::new((1, 3), (1, 3)), LexErrorKind::UnknownChar('\\'))));
+        assert_eq!(tokenize("a \\\nb").unwrap_err().kind, LexErrorKind::UnknownChar('\\'));
+    }
+
+    #[test]
+    fn tab_width() {
+        let source = "\tfoo\n  \tbar\n\t\tbaz x\tq";
+        let tokens = tokenize(source).unwrap();
+        assert_eq!(tokens[0].position().start, (1, 2));
+        assert_eq!(tokens[1].position().start, (2, 4));
+
+        let options = LexerOptions { tab_width: 4, ..Default::default() };
+        let tokens = tokenize_with_options(source, &options).unwrap();
+        assert_eq!(tokens[0], Token::Identifier(Position::new((1, 5), (1, 7)), "foo".to_string()));
+        // a tab after a partial indent only fills up to the stop
+        assert_eq!(tokens[1].position().start, (2, 5));
+        assert_eq!(tokens[2].position().start, (3, 9));
+        assert_eq!(tokens[4].position().start, (3, 17));
+    }
+
+    #[test]
+    fn position_width() {
+        let tokens = tokenize("a == \"é\"\n\"x\ny\"").unwrap();
+        assert_eq!(tokens[1].position().width(), 2);
+        assert!(!tokens[1].position().is_multiline());
+        assert_eq!(tokens[1].position().byte_len(), 2);
+
+        assert_eq!(tokens[2].position().width(), 3);
+        assert_eq!(tokens[2].position().byte_len(), 4);
+
+        assert!(tokens[3].position().is_multiline());
+        assert_eq!(tokens[3].position().width(), 0);
+        assert_eq!(tokens[3].position().byte_len(), 5);
+    }
+
+    #[test]
+    fn position_merge() {
+        let source = "f( x )";
+        let tokens = tokenize(source).unwrap();
+        let (open, close) = (tokens[1].position(), tokens[3].position());
+        let span = open.merge(close);
+        assert_eq!(span, Position::new((1, 2), (1, 6)));
+        assert_eq!(&source[span.byte_range()], "( x )");
+        assert_eq!(close.merge(open).byte_range(), span.byte_range());
+
+        // a span inside the other leaves it unchanged
+        assert_eq!(span.merge(tokens[2].position()), span);
+    }
+
+    #[test]
+    fn position_overflow() {
+        // positions saturate at usize::MAX instead of wrapping or panicking
+        let options = LexerOptions { start: Some((usize::MAX - 1, usize::MAX - 2)), tab_width: usize::MAX, ..Default::default() };
+        let source = "ab == c\t?? d <<= \"x\ny\" ...\n/*\n*/ @";
+        let (tokens, errors) = tokenize_all_with_options(source, &options);
+        assert_eq!(tokens[0], Token::Identifier(Position::new((usize::MAX - 1, usize::MAX - 2), (usize::MAX - 1, usize::MAX - 1)), "ab".to_string()));
+        assert_eq!(tokens[1].position(), &Position::new((usize::MAX - 1, usize::MAX), (usize::MAX - 1, usize::MAX)));
+        assert_eq!(tokens.last().unwrap().position().start, (usize::MAX, 5));
+        assert_eq!(errors[0].position.start, (usize::MAX, 4));
+        assert!(tokens.iter().all(|token| token.position().width() >= 1 || token.position().is_multiline()));
+        assert!(lint_confusables(&tokens).is_empty());
+        assert!(tokenize_with_warnings(source, &LexerOptions { max_line_length: Some(1), ..options.clone() }).is_err());
+    }
+
+    #[test]
+    fn punctuation_str() {
+        let source = "( ) { } ; , : :: .";
+        let tokens = tokenize(source).unwrap();
+        let strs = tokens.iter().map(|token| token.punctuation_str().unwrap_or("")).collect::<Vec<_>>();
+        assert_eq!(strs.join(" "), format!("{source} "));
+
+        for source in ["a", "1", "\"s\"", "..", "..=", "...", "?.", "->", "=>", "+", "=", "<"] {
+            assert_eq!(tokenize(source).unwrap()[0].punctuation_str(), None, "{source}");
+        }
+
+        let options = LexerOptions { statement_terminator: Some('\n'), ..Default::default() };
+        assert_eq!(tokenize_with_options("a\n", &options).unwrap()[1].punctuation_str(), Some(";"));
+    }
+
+    #[test]
+    fn source_ids() {
+        let (a, b) = (tokenize_with_source("x @", 1), tokenize_with_source("x y", 2).unwrap());
+        assert_eq!(a.as_ref().unwrap_err().position.source_id(), 1);
+        assert!(b.iter().all(|token| token.position().source_id() == 2));
+        assert_eq!(tokenize("x").unwrap()[0].position().source_id(), 0);
+
+        // the same (row, col) in two files is not the same position
+        let a = tokenize_with_source("x", 1).unwrap();
+        assert_eq!(a[0].position().start, b[0].position().start);
+        assert_ne!(a[0].position(), b[0].position());
+        assert_eq!(b[0].position().merge(b[1].position()).source_id(), 2);
+
+        let options = LexerOptions { source_id: 3, attributes: true, ..Default::default() };
+        let (tokens, errors) = tokenize_all_with_options("@a(b) $\n\t c", &options);
+        let Token::Attribute(_, attr) = &tokens[0] else { panic!("{:?}", tokens[0]) };
+        assert_eq!(attr.1[0].position().source_id(), 3);
+        assert!(tokens.iter().all(|token| token.position().source_id() == 3));
+        assert!(errors.iter().all(|err| err.position.source_id() == 3));
+    }
+
+    #[test]
+    #[should_panic(expected = "different sources")]
+    fn merge_across_sources() {
+        let (a, b) = (tokenize_with_source("x", 1).unwrap(), tokenize_with_source("x", 2).unwrap());
+        a[0].position().merge(b[0].position());
+    }
+
+    #[cfg(feature = "arena")]
+    #[test]
+    fn tokenize_in_arena() {
+        let bump = bumpalo::Bump::new();
+        let options = LexerOptions { attributes: true, ..Default::default() };
+        let source = "@a(b) x += \"s\"";
+        let tokens = tokenize_in(source, &options, &bump).unwrap();
+        assert_eq!(&tokens[..], &tokenize_with_options(source, &options).unwrap()[..]);
+        assert!(bump.allocated_bytes() >= tokens.len() * std::mem::size_of::<Token>());
+
+        assert_eq!(tokenize_in("a $", &options, &bump).unwrap_err().kind, LexErrorKind::UnknownChar('$'));
+    }
+
+    #[test]
+    fn position_getters() {
+        let tokens = tokenize("a\n  <<= b").unwrap();
+        let pos = tokens[1].position();
+        assert_eq!(pos.start(), (2, 3));
+        assert_eq!(pos.end(), (2, 5));
+        assert_eq!((pos.row(), pos.col()), pos.start());
+        assert_eq!(tokens[0].position().end(), (1, 1));
+    }
+
+    #[test]
+    fn position_contains() {
+        let tokens = tokenize("a <<= b").unwrap();
+        let pos = tokens[1].position();
+        assert!(pos.contains(1, 3) && pos.contains(1, 4) && pos.contains(1, 5));
+        assert!(!pos.contains(1, 2) && !pos.contains(1, 6));
+        assert!(!pos.contains(2, 4));
+
+        // spans (2, 3)..(4, 3)
+        let tokens = tokenize("x\n  \"ab\n\ncd\"").unwrap();
+        let pos = tokens[1].position();
+        assert!(pos.contains(2, 3) && pos.contains(2, 100));
+        assert!(pos.contains(3, 1) && pos.contains(3, 0));
+        assert!(pos.contains(4, 1) && pos.contains(4, 3));
+        assert!(!pos.contains(2, 2) && !pos.contains(4, 4));
+        assert!(!pos.contains(1, 5) && !pos.contains(5, 1));
+    }
+
+    #[test]
+    fn pipe_forward() {
+        let kinds = |source, options: &LexerOptions| tokenize_with_options(source, options).unwrap().iter().map(Token::kind).collect::<Vec<_>>();
+        let options = LexerOptions { pipe_forward: true, ..Default::default() };
+        assert_eq!(kinds("a |> b", &options), [TokenKind::Identifier, TokenKind::PipeForward, TokenKind::Identifier, TokenKind::Eof]);
+        assert_eq!(kinds("a | b", &options), [TokenKind::Identifier, TokenKind::Or, TokenKind::Identifier, TokenKind::Eof]);
+        assert_eq!(kinds("a || b", &options), [TokenKind::Identifier, TokenKind::OrOr, TokenKind::Identifier, TokenKind::Eof]);
+        assert_eq!(kinds("a ||> b", &options), [TokenKind::Identifier, TokenKind::OrOr, TokenKind::Gt, TokenKind::Identifier, TokenKind::Eof]);
+        assert_eq!(kinds("a | > b", &options), [TokenKind::Identifier, TokenKind::Or, TokenKind::Gt, TokenKind::Identifier, TokenKind::Eof]);
+        assert_eq!(kinds("a |> b", &LexerOptions::default()), [TokenKind::Identifier, TokenKind::Or, TokenKind::Gt, TokenKind::Identifier, TokenKind::Eof]);
+
+        let tokens = tokenize_with_options("x |> f", &options).unwrap();
+        assert_eq!(tokens[1], Token::PipeForward(Position::new((1, 3), (1, 4))));
+        assert_eq!(tokens[2].position().start, (1, 6));
+    }
+
+    #[test]
+    fn zero_based() {
+        let options = LexerOptions { zero_based: true, attributes: true, max_line_length: Some(3), ..Default::default() };
+        let tokens = tokenize_with_options("foo\n@a(b)", &options).unwrap();
+        assert_eq!(tokens[0], Token::Identifier(Position::new((0, 0), (0, 2)), "foo".to_string()));
+        let Token::Attribute(pos, attr) = &tokens[1] else { panic!("{:?}", tokens[1]) };
+        assert_eq!(pos, &Position::new((1, 0), (1, 4)));
+        assert_eq!(attr.1[0].position().start, (1, 3));
+        assert_eq!(tokens[2].position().start, (1, 5));
+        assert_eq!(token_at(&tokens, 0, 0), Some(&tokens[0]));
+
+        assert_eq!(tokenize_with_options("a\n $", &options).unwrap_err().position.start, (1, 1));
+        let (tokens, errors) = tokenize_all_with_options("a\n $ \t\n \tb", &options);
+        assert_eq!(errors[0].position.start, (1, 1));
+        assert_eq!(errors[1].kind, LexErrorKind::MixedIndentation);
+        assert_eq!(errors[1].position.start, (2, 0));
+        assert_eq!(tokens.last().unwrap().position().start, (2, 3));
+
+        let (_, warnings) = tokenize_with_warnings("a\nlong", &options).unwrap();
+        assert_eq!(warnings, [LexWarning::LineTooLong { row: 1, length: 4 }]);
+
+        // an explicit start is 0-based too, and every line after it starts at col 0
+        let options = LexerOptions { zero_based: true, start: Some((0, 0)), tab_width: 4, ..Default::default() };
+        let starts = |source, options: &LexerOptions| tokenize_with_options(source, options).unwrap().iter().map(|token| token.position().start).collect::<Vec<_>>();
+        assert_eq!(starts("a b\nc", &options), [(0, 0), (0, 2), (1, 0), (1, 1)]);
+        assert_eq!(starts("\"x\ny\" /*\n*/a\n\tb", &options), [(0, 0), (2, 2), (3, 4), (3, 5)]);
+        assert_eq!(starts("a\nb", &LexerOptions { start: Some((5, 3)), ..options }), [(5, 3), (6, 0), (6, 1)]);
+    }
+
+    #[test]
+    fn pipeline_arrows() {
+        let kinds = |source, options: &LexerOptions| tokenize_with_options(source, options).unwrap().iter().map(Token::kind).collect::<Vec<_>>();
+        let options = LexerOptions { pipeline_arrows: true, ..Default::default() };
+        assert_eq!(kinds("a ->> b", &options), [TokenKind::Identifier, TokenKind::ArrowGt, TokenKind::Identifier, TokenKind::Eof]);
+        assert_eq!(kinds("a <<- b", &options), [TokenKind::Identifier, TokenKind::LShiftSub, TokenKind::Identifier, TokenKind::Eof]);
+        // the two-char operators and their other three-char forms are unchanged
+        for source in ["a -> b", "a << b", "a <<= b", "a -> > b", "a << - b"] {
+            assert_eq!(kinds(source, &options), kinds(source, &LexerOptions::default()), "{source}");
+        }
+
+        assert_eq!(kinds("a ->> b", &LexerOptions::default()), [TokenKind::Identifier, TokenKind::Arrow, TokenKind::Gt, TokenKind::Identifier, TokenKind::Eof]);
+        assert_eq!(kinds("a <<- b", &LexerOptions::default()), [TokenKind::Identifier, TokenKind::LShift, TokenKind::Sub, TokenKind::Identifier, TokenKind::Eof]);
+
+        let tokens = tokenize_with_options("x ->> f <<- y", &options).unwrap();
+        assert_eq!(tokens[1], Token::ArrowGt(Position::new((1, 3), (1, 5))));
+        assert_eq!(tokens[3], Token::LShiftSub(Position::new((1, 9), (1, 11))));
+        assert_eq!(tokens[4].position().start, (1, 13));
+    }
+
     #[test]
     fn file() -> Result<(), std::io::Error> {
         let filename = "main.hl";
         let input = std::fs::read(filename)?;
         let input = std::str::from_utf8(input.as_slice()).expect("should be utf8").to_string();
 
-        let tokens = tokenize(input);
+        let tokens = tokenize(&input).unwrap();
         for token in tokens {
             eprintln!("{:?}", token);
         }